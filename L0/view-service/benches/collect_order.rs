@@ -0,0 +1,79 @@
+//! Compares the single query order assembly with the query per table one.
+//!
+//! Requires a running Postgres with the service tables, the connection is configured
+//! with `POSTGRES_HOST`, `POSTGRES_PORT`, `POSTGRES_USER`, `POSTGRES_PASSWORD` and `POSTGRES_DB`,
+//! by default the docker compose db is used.
+
+use std::env;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use tokio::runtime::Runtime;
+use tokio_postgres::{Client, NoTls};
+use view_service::{db, schemas::Order};
+
+const BENCH_ORDER_UID: &str = "collect-order-bench";
+const BENCH_ITEMS: i64 = 20;
+
+async fn connect() -> Result<Client, tokio_postgres::Error> {
+    let var = |name: &str, default: &str| env::var(name).unwrap_or_else(|_| default.to_string());
+    let config = format!(
+        "host={} port={} user={} password={} dbname={}",
+        var("POSTGRES_HOST", "localhost"),
+        var("POSTGRES_PORT", "5432"),
+        var("POSTGRES_USER", "postgres"),
+        var("POSTGRES_PASSWORD", "postgres"),
+        var("POSTGRES_DB", "postgres"),
+    );
+
+    let (client, connection) = tokio_postgres::connect(&config, NoTls).await?;
+    tokio::spawn(connection);
+    Ok(client)
+}
+
+// stores an order with several items unless it is already there
+async fn seed(client: &mut Client) {
+    let mut order: Order = serde_json::from_str(include_str!("../model/model.json")).unwrap();
+    order.order_uid = BENCH_ORDER_UID.to_string();
+    let item = order.items.pop().unwrap();
+    for i in 0..BENCH_ITEMS {
+        let mut item = item.clone();
+        item.chrt_id += i;
+        item.rid = format!("{}-{}", item.rid, i);
+        order.items.push(item);
+    }
+
+    match db::inser_order_tx(client, &order, None).await {
+        Ok(_) => {}
+        Err(e) if e.is_unique_violation() => {}
+        Err(e) => panic!("failed to seed the bench order: {}", e),
+    }
+}
+
+fn collect_order(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut client = match rt.block_on(connect()) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("skipping collect_order bench, no db available: {}", e);
+            return;
+        }
+    };
+    rt.block_on(seed(&mut client));
+
+    let mut group = c.benchmark_group("collect_order");
+    group.bench_function("single_query", |b| {
+        b.to_async(&rt)
+            .iter(|| async { db::collect_order(&client, BENCH_ORDER_UID).await.unwrap() })
+    });
+    group.bench_function("query_per_table", |b| {
+        b.to_async(&rt).iter(|| async {
+            db::collect_order_sequential(&client, BENCH_ORDER_UID)
+                .await
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, collect_order);
+criterion_main!(benches);
@@ -0,0 +1,3 @@
+-- indexes backing the stats, the date range is served by orders_date_created_order_uid_idx
+CREATE INDEX IF NOT EXISTS items_nm_id_idx ON items (nm_id);
+CREATE INDEX IF NOT EXISTS deliveries_region_idx ON deliveries (region);
@@ -0,0 +1,21 @@
+-- audit of the customer erasures, kept after the orders themselves are deleted
+CREATE TABLE IF NOT EXISTS pii_erasures
+(
+    id           BIGSERIAL NOT NULL PRIMARY KEY,
+    customer_id  VARCHAR NOT NULL,
+    order_uids   VARCHAR[] NOT NULL,
+    reason       VARCHAR NOT NULL,
+    requested_by VARCHAR NOT NULL,
+    created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS pii_erasures_customer_id_idx ON pii_erasures (customer_id);
+
+CREATE OR REPLACE FUNCTION pii_erasures_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'pii_erasures rows can not be modified';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER pii_erasures_append_only
+    BEFORE UPDATE OR DELETE ON pii_erasures
+    FOR EACH ROW EXECUTE FUNCTION pii_erasures_append_only();
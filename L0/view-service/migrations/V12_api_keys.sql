@@ -0,0 +1,14 @@
+-- keys of the API clients, only the SHA-256 of a key is stored
+CREATE TABLE IF NOT EXISTS api_keys
+(
+    id           BIGSERIAL NOT NULL PRIMARY KEY,
+    name         VARCHAR NOT NULL,
+    key_hash     BYTEA NOT NULL UNIQUE,
+    scopes       VARCHAR[] NOT NULL CHECK (scopes <@ ARRAY['read', 'write', 'admin']::VARCHAR[]),
+    -- token bucket of the per-key rate limit
+    rate_per_sec DOUBLE PRECISION NOT NULL CHECK (rate_per_sec > 0),
+    burst        INTEGER NOT NULL CHECK (burst > 0),
+    created_at   TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    -- revoked keys are kept, so it is known who has made the past requests
+    revoked_at   TIMESTAMP WITH TIME ZONE
+);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys
+(
+    idempotency_key VARCHAR NOT NULL PRIMARY KEY,
+    order_uid       VARCHAR NOT NULL,
+    created_at      TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    FOREIGN KEY (order_uid) REFERENCES orders (order_uid)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- foreign keys are not indexed by postgres, while every order read filters items by order_uid
+CREATE INDEX IF NOT EXISTS items_order_uid_idx ON items (order_uid);
@@ -0,0 +1,31 @@
+-- every order moves through the status lifecycle, existing orders start as created
+ALTER TABLE orders
+    ADD COLUMN IF NOT EXISTS status VARCHAR NOT NULL DEFAULT 'created'
+        CHECK (status IN ('created', 'paid', 'shipped', 'delivered', 'cancelled'));
+
+CREATE TABLE IF NOT EXISTS order_events
+(
+    id          BIGSERIAL NOT NULL PRIMARY KEY,
+    order_uid   VARCHAR NOT NULL,
+    -- NULL for the creation of the order
+    from_status VARCHAR,
+    to_status   VARCHAR NOT NULL,
+    reason      VARCHAR,
+    created_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    FOREIGN KEY (order_uid) REFERENCES orders (order_uid)
+);
+CREATE INDEX IF NOT EXISTS order_events_order_uid_id_idx ON order_events (order_uid, id);
+
+-- the history is append-only
+CREATE OR REPLACE FUNCTION order_events_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'order_events rows can not be modified';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER order_events_append_only
+    BEFORE UPDATE OR DELETE ON order_events
+    FOR EACH ROW EXECUTE FUNCTION order_events_append_only();
+
+INSERT INTO order_events (order_uid, to_status, created_at)
+SELECT order_uid, 'created', COALESCE(date_created, now()) FROM orders;
@@ -0,0 +1,15 @@
+-- the history is removed along with its order
+ALTER TABLE order_events DROP CONSTRAINT IF EXISTS order_events_order_uid_fkey;
+ALTER TABLE order_events
+    ADD CONSTRAINT order_events_order_uid_fkey FOREIGN KEY (order_uid) REFERENCES orders (order_uid)
+        ON DELETE CASCADE;
+
+-- events are still never modified, and only deleted by the cascade
+CREATE OR REPLACE FUNCTION order_events_append_only() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'DELETE' AND NOT EXISTS (SELECT 1 FROM orders WHERE order_uid = OLD.order_uid) THEN
+        RETURN OLD;
+    END IF;
+    RAISE EXCEPTION 'order_events rows can not be modified';
+END;
+$$ LANGUAGE plpgsql;
@@ -0,0 +1,419 @@
+//! API key authentication and per-key rate limiting.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    num::NonZeroUsize,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use lru::LruCache;
+use parking_lot::Mutex;
+use ring::{
+    digest::{digest, SHA256},
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{Client, Row};
+
+use crate::{error::AppError, pii::Role, repository::OrderRepository, AppState};
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+// generated keys are easy to tell apart from other secrets
+const KEY_PREFIX: &str = "vs_";
+const KEY_BYTES: usize = 32;
+
+// revoked keys are still accepted for this long
+const KEY_CACHE_TTL: Duration = Duration::from_secs(60);
+const KEY_CACHE_CAPACITY: usize = 1024;
+
+// unknown keys are looked up in the db every time, so the failed attempts are limited globally,
+// the cached keys keep working while the limit is exceeded
+const FAILED_AUTH_RATE_PER_SEC: f64 = 5.0;
+const FAILED_AUTH_BURST: f64 = 50.0;
+
+/// Permission granted to an API key.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // orders, history, stats and the UI
+    Read,
+    // order creation, status changes and removal
+    Write,
+    // everything, including the customer erasure and the unmasked PII
+    Admin,
+}
+
+impl Scope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Write => "write",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownScope(String);
+
+impl fmt::Display for UnknownScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown API key scope {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnknownScope {}
+
+impl FromStr for Scope {
+    type Err = UnknownScope;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "read" => Ok(Scope::Read),
+            "write" => Ok(Scope::Write),
+            "admin" => Ok(Scope::Admin),
+            _ => Err(UnknownScope(value.to_string())),
+        }
+    }
+}
+
+/// Stored API key, only the SHA-256 of the key itself is kept.
+#[derive(Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    // token bucket refill rate and size
+    pub rate_per_sec: f64,
+    pub burst: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl ApiKey {
+    fn from_row(row: &Row) -> ApiKey {
+        // the scopes are checked by the db, so an unknown one is only possible after a downgrade
+        let scopes = row
+            .get::<_, Vec<String>>("scopes")
+            .iter()
+            .filter_map(|scope| match scope.parse() {
+                Ok(scope) => Some(scope),
+                Err(e) => {
+                    tracing::warn!("ignoring {}", e);
+                    None
+                }
+            })
+            .collect();
+        ApiKey {
+            id: row.get("id"),
+            name: row.get("name"),
+            scopes,
+            rate_per_sec: row.get("rate_per_sec"),
+            burst: row.get("burst"),
+            created_at: row.get("created_at"),
+        }
+    }
+
+    /// The admin scope grants all the others.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope) || self.scopes.contains(&Scope::Admin)
+    }
+
+    /// Role used for the PII masking, only admin keys see the PII.
+    pub fn role(&self) -> Role {
+        if self.allows(Scope::Admin) {
+            Role::Admin
+        } else {
+            Role::Viewer
+        }
+    }
+}
+
+/// Parameters of a key created with `--create-api-key`.
+#[derive(Debug, Clone)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub rate_per_sec: f64,
+    pub burst: i32,
+}
+
+/// Generates a key, stores its hash and returns the key, it can't be recovered later.
+pub async fn create_key(
+    repo: &dyn OrderRepository,
+    new: &NewApiKey,
+) -> Result<(String, ApiKey), AppError> {
+    let mut bytes = [0u8; KEY_BYTES];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("system random generator is available");
+    let key = format!("{KEY_PREFIX}{}", URL_SAFE_NO_PAD.encode(bytes));
+    let stored = repo.create_api_key(new, &hash_key(&key)).await?;
+    Ok((key, stored))
+}
+
+pub fn hash_key(key: &str) -> Vec<u8> {
+    digest(&SHA256, key.as_bytes()).as_ref().to_vec()
+}
+
+pub async fn find_key(conn: &Client, key_hash: &[u8]) -> Result<Option<ApiKey>, AppError> {
+    let row = conn
+        .query_opt(
+            "SELECT id, name, scopes, rate_per_sec, burst, created_at
+            FROM api_keys WHERE key_hash = $1 AND revoked_at IS NULL",
+            &[&key_hash],
+        )
+        .await?;
+    Ok(row.as_ref().map(ApiKey::from_row))
+}
+
+pub async fn insert_key(
+    conn: &Client,
+    new: &NewApiKey,
+    key_hash: &[u8],
+) -> Result<ApiKey, AppError> {
+    let scopes: Vec<&str> = new.scopes.iter().map(|scope| scope.as_str()).collect();
+    let row = conn
+        .query_one(
+            "INSERT INTO api_keys (name, key_hash, scopes, rate_per_sec, burst)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, scopes, rate_per_sec, burst, created_at",
+            &[&new.name, &key_hash, &scopes, &new.rate_per_sec, &new.burst],
+        )
+        .await?;
+    Ok(ApiKey::from_row(&row))
+}
+
+// found key and the lookup time
+type LookedUp = (Arc<ApiKey>, Instant);
+
+/// Known keys and the rate limit buckets, the keys themselves are stored in the repository.
+pub struct Auth {
+    // recently found keys by their hash with the lookup time, unknown keys are not cached
+    // so that a flood of them can't evict the valid ones
+    keys: Mutex<LruCache<Vec<u8>, LookedUp>>,
+    buckets: Mutex<HashMap<i64, Bucket>>,
+    // shared by all the failed attempts
+    failures: Mutex<Bucket>,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Auth::new()
+    }
+}
+
+impl Auth {
+    pub fn new() -> Auth {
+        Auth {
+            keys: Mutex::new(LruCache::new(
+                NonZeroUsize::new(KEY_CACHE_CAPACITY).expect("capacity is positive"),
+            )),
+            buckets: Mutex::new(HashMap::new()),
+            failures: Mutex::new(Bucket {
+                tokens: FAILED_AUTH_BURST,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    async fn lookup(
+        &self,
+        repo: &dyn OrderRepository,
+        key: &str,
+    ) -> Result<Option<Arc<ApiKey>>, AppError> {
+        let key_hash = hash_key(key);
+        if let Some((found, at)) = self.keys.lock().get(&key_hash) {
+            if at.elapsed() < KEY_CACHE_TTL {
+                return Ok(Some(found.clone()));
+            }
+        }
+
+        // the db is not queried while the failed attempts are over the limit
+        let retry_after = self
+            .failures
+            .lock()
+            .wait_time(FAILED_AUTH_RATE_PER_SEC, FAILED_AUTH_BURST);
+        if retry_after > 0 {
+            tracing::debug!("failed authentication attempts are rate limited");
+            return Err(AppError::RateLimited { retry_after });
+        }
+
+        match repo.api_key(&key_hash).await? {
+            Some(found) => {
+                let found = Arc::new(found);
+                self.keys
+                    .lock()
+                    .put(key_hash, (found.clone(), Instant::now()));
+                Ok(Some(found))
+            }
+            None => {
+                self.failures
+                    .lock()
+                    .take(FAILED_AUTH_RATE_PER_SEC, FAILED_AUTH_BURST);
+                Ok(None)
+            }
+        }
+    }
+
+    fn take_token(&self, key: &ApiKey) -> Quota {
+        let mut buckets = self.buckets.lock();
+        let bucket = buckets.entry(key.id).or_insert_with(|| Bucket {
+            tokens: key.burst as f64,
+            updated: Instant::now(),
+        });
+        bucket.take(key.rate_per_sec, key.burst as f64)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, rate: f64, burst: f64) {
+        let now = Instant::now();
+        let refilled = now.duration_since(self.updated).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refilled).min(burst);
+        self.updated = now;
+    }
+
+    // seconds until a token is available, 0 if there is one already
+    fn wait_time(&mut self, rate: f64, burst: f64) -> u64 {
+        self.refill(rate, burst);
+        ((1.0 - self.tokens).max(0.0) / rate).ceil() as u64
+    }
+
+    fn take(&mut self, rate: f64, burst: f64) -> Quota {
+        self.refill(rate, burst);
+
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        Quota {
+            allowed,
+            limit: burst as u64,
+            remaining: self.tokens as u64,
+            retry_after: ((1.0 - self.tokens).max(0.0) / rate).ceil() as u64,
+            reset: ((burst - self.tokens) / rate).ceil() as u64,
+        }
+    }
+}
+
+// state of the bucket after a request, reported in the `RateLimit-*` headers
+struct Quota {
+    allowed: bool,
+    limit: u64,
+    remaining: u64,
+    // seconds until the next token and until the bucket is full
+    retry_after: u64,
+    reset: u64,
+}
+
+impl Quota {
+    fn add_headers(&self, headers: &mut HeaderMap) {
+        for (name, value) in [
+            ("ratelimit-limit", self.limit),
+            ("ratelimit-remaining", self.remaining),
+            ("ratelimit-reset", self.reset),
+        ] {
+            headers.insert(name, HeaderValue::from(value));
+        }
+    }
+}
+
+/// Scope required by a group of routes, the state of the authentication middleware.
+#[derive(Clone)]
+pub struct Required {
+    state: Arc<AppState>,
+    scope: Scope,
+}
+
+impl Required {
+    pub fn new(state: &Arc<AppState>, scope: Scope) -> Required {
+        Required {
+            state: state.clone(),
+            scope,
+        }
+    }
+}
+
+/// Checks the API key of the request, its scope and rate limit.
+///
+/// The key is passed in the `X-API-Key` header or as `Authorization: Bearer <key>`,
+/// the authenticated key is put into the request extensions. Does nothing if the auth is disabled.
+pub async fn authenticate(
+    State(required): State<Required>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(auth) = &required.state.auth else {
+        return next.run(request).await;
+    };
+
+    let key = match presented_key(request.headers()) {
+        Some(key) => key,
+        None => return AppError::Unauthorized("API key is required".to_string()).into_response(),
+    };
+    let key = match auth.lookup(required.state.repo.as_ref(), key).await {
+        Ok(Some(key)) => key,
+        Ok(None) => {
+            return AppError::Unauthorized("API key is invalid or revoked".to_string())
+                .into_response()
+        }
+        Err(e) => return e.into_response(),
+    };
+    if !key.allows(required.scope) {
+        return AppError::Forbidden(format!(
+            "API key {} has no {} scope",
+            key.name, required.scope
+        ))
+        .into_response();
+    }
+
+    let quota = auth.take_token(&key);
+    let mut response = if quota.allowed {
+        request.extensions_mut().insert(key);
+        next.run(request).await
+    } else {
+        tracing::debug!("API key {} is rate limited", key.name);
+        AppError::RateLimited {
+            retry_after: quota.retry_after,
+        }
+        .into_response()
+    };
+    quota.add_headers(response.headers_mut());
+    response
+}
+
+fn presented_key(headers: &HeaderMap) -> Option<&str> {
+    if let Some(key) = headers.get(API_KEY_HEADER) {
+        return key.to_str().ok().map(str::trim);
+    }
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
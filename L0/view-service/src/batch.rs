@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::AppError,
+    idempotency,
+    pii::{self, Role},
+    schemas::Order,
+    validation::validate_order,
+    AppState,
+};
+
+pub const MAX_BATCH_SIZE: usize = 10_000;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchMode {
+    // every valid order is stored, failed ones are reported
+    #[default]
+    Partial,
+    // either all the orders are stored or none of them
+    Atomic,
+}
+
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BatchParams {
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryStatus {
+    Created,
+    // identical order is already stored or appears earlier in the batch
+    Duplicate,
+    // order with the same uid but different content is already stored or in the batch
+    Conflict,
+    Invalid,
+    Failed,
+    // valid order that is not stored as the atomic batch has been rejected
+    NotInserted,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct EntryReport {
+    pub index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order_uid: Option<String>,
+    pub status: EntryStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+impl EntryReport {
+    fn new(index: usize, order_uid: Option<&str>, status: EntryStatus) -> EntryReport {
+        EntryReport {
+            index,
+            order_uid: order_uid.map(str::to_string),
+            status,
+            error: None,
+            details: None,
+        }
+    }
+
+    fn with_error(mut self, error: impl Into<String>, details: Option<Value>) -> EntryReport {
+        self.error = Some(error.into());
+        self.details = details;
+        self
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct BatchReport {
+    pub mode: BatchMode,
+    pub total: usize,
+    pub created: usize,
+    pub duplicates: usize,
+    pub failed: usize,
+    pub results: Vec<EntryReport>,
+}
+
+impl BatchReport {
+    fn new(mode: BatchMode, mut results: Vec<EntryReport>) -> BatchReport {
+        results.sort_by_key(|r| r.index);
+        let count = |status| results.iter().filter(|r| r.status == status).count();
+        let created = count(EntryStatus::Created);
+        let duplicates = count(EntryStatus::Duplicate);
+        BatchReport {
+            mode,
+            total: results.len(),
+            created,
+            duplicates,
+            failed: results.len() - created - duplicates,
+            results,
+        }
+    }
+}
+
+/// Splits the request body into separate orders, a malformed order doesn't affect the others.
+///
+/// The body is either a JSON array of orders or NDJSON with an order per line.
+pub fn parse_batch(body: &[u8], ndjson: bool) -> Result<Vec<Result<Order, String>>, AppError> {
+    let entries: Vec<Result<Order, String>> = if ndjson {
+        std::str::from_utf8(body)
+            .map_err(|_| AppError::BadRequest("NDJSON body must be valid UTF-8".to_string()))?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|e| e.to_string()))
+            .collect()
+    } else {
+        serde_json::from_slice::<Vec<Value>>(body)
+            .map_err(|e| AppError::BadRequest(format!("body must be a JSON array of orders: {e}")))?
+            .into_iter()
+            .map(|value| serde_json::from_value(value).map_err(|e| e.to_string()))
+            .collect()
+    };
+
+    if entries.is_empty() {
+        return Err(AppError::BadRequest("batch contains no orders".to_string()));
+    }
+    if entries.len() > MAX_BATCH_SIZE {
+        return Err(AppError::BadRequest(format!(
+            "batch is limited to {MAX_BATCH_SIZE} orders"
+        )));
+    }
+    Ok(entries)
+}
+
+/// Validates and stores a batch of orders.
+///
+/// Valid orders are inserted with a multi-row statement per table in a single transaction.
+/// In the partial mode a failed bulk insert falls back to inserting orders one by one.
+pub async fn ingest_batch(
+    state: &AppState,
+    entries: Vec<Result<Order, String>>,
+    mode: BatchMode,
+    role: Role,
+) -> Result<BatchReport, AppError> {
+    let mut results = Vec::with_capacity(entries.len());
+    let mut candidates: Vec<(usize, Order)> = Vec::with_capacity(entries.len());
+    // the first occurrence of every order_uid among the candidates
+    let mut seen: HashMap<String, usize> = HashMap::new();
+
+    for (index, entry) in entries.into_iter().enumerate() {
+        let order = match entry {
+            Ok(order) => order,
+            Err(e) => {
+                results.push(
+                    EntryReport::new(index, None, EntryStatus::Invalid)
+                        .with_error(format!("malformed order: {e}"), None),
+                );
+                continue;
+            }
+        };
+        if let Err(errors) = validate_order(&order) {
+            results.push(
+                EntryReport::new(index, Some(&order.order_uid), EntryStatus::Invalid)
+                    .with_error("validation failed", serde_json::to_value(errors).ok()),
+            );
+            continue;
+        }
+
+        match seen.get(&order.order_uid) {
+            Some(&first) => {
+                let differences = idempotency::order_diff(&candidates[first].1, &order);
+                results.push(repeated_order_report(
+                    index,
+                    &order.order_uid,
+                    differences,
+                    role,
+                ));
+            }
+            None => {
+                seen.insert(order.order_uid.clone(), candidates.len());
+                candidates.push((index, order));
+            }
+        }
+    }
+
+    // already stored orders are resolved the same way as single resubmissions
+    let uids: Vec<&str> = candidates
+        .iter()
+        .map(|(_, o)| o.order_uid.as_str())
+        .collect();
+    let mut stored: HashMap<String, Order> = state
+        .repo
+        .get_many(&uids)
+        .await?
+        .into_iter()
+        .map(|order| (order.order_uid.clone(), order))
+        .collect();
+    let mut to_insert = Vec::with_capacity(candidates.len());
+    for (index, order) in candidates {
+        if let Some(existing) = stored.remove(&order.order_uid) {
+            let differences = idempotency::order_diff(&existing, &order);
+            results.push(repeated_order_report(
+                index,
+                &order.order_uid,
+                differences,
+                role,
+            ));
+        } else {
+            to_insert.push((index, order));
+        }
+    }
+
+    let rejected = results.iter().any(|r| r.status != EntryStatus::Duplicate);
+    if mode == BatchMode::Atomic && rejected {
+        results.extend(to_insert.iter().map(|(index, order)| {
+            EntryReport::new(*index, Some(&order.order_uid), EntryStatus::NotInserted)
+        }));
+        return Err(batch_rejected(BatchReport::new(mode, results)));
+    }
+
+    let orders: Vec<&Order> = to_insert.iter().map(|(_, order)| order).collect();
+    match state.repo.insert_many(&orders).await {
+        Ok(_) => {
+            tracing::debug!("batch of {} orders commited", orders.len());
+            results.extend(to_insert.iter().map(|(index, order)| {
+                EntryReport::new(*index, Some(&order.order_uid), EntryStatus::Created)
+            }));
+        }
+        // some of the orders have been stored concurrently
+        Err(e) if mode == BatchMode::Atomic && e.is_unique_violation() => {
+            return Err(AppError::Conflict {
+                message: "Some of the orders have been concurrently created".to_string(),
+                details: None,
+            });
+        }
+        Err(e) if mode == BatchMode::Atomic => return Err(e),
+        Err(e) => {
+            tracing::debug!("bulk insert failed, inserting orders one by one: {}", e);
+            for (index, order) in &to_insert {
+                let report = insert_single(state, *index, order, role).await;
+                results.push(report);
+            }
+        }
+    }
+
+    Ok(BatchReport::new(mode, results))
+}
+
+async fn insert_single(state: &AppState, index: usize, order: &Order, role: Role) -> EntryReport {
+    let failed = || {
+        EntryReport::new(index, Some(&order.order_uid), EntryStatus::Failed)
+            .with_error("failed to store the order", None)
+    };
+    match state.repo.insert(order, None).await {
+        Ok(_) => EntryReport::new(index, Some(&order.order_uid), EntryStatus::Created),
+        // stored concurrently after the lookup
+        Err(e) if e.is_unique_violation() => match state.repo.get(&order.order_uid).await {
+            Ok(existing) => repeated_order_report(
+                index,
+                &order.order_uid,
+                idempotency::order_diff(&existing, order),
+                role,
+            ),
+            Err(_) => failed(),
+        },
+        Err(e) => {
+            tracing::warn!("failed to store order {}: {}", order.order_uid, e);
+            failed()
+        }
+    }
+}
+
+fn repeated_order_report(
+    index: usize,
+    order_uid: &str,
+    differences: Vec<idempotency::FieldDiff>,
+    role: Role,
+) -> EntryReport {
+    let differences = pii::redact_diff(differences, role);
+    if differences.is_empty() {
+        EntryReport::new(index, Some(order_uid), EntryStatus::Duplicate)
+    } else {
+        EntryReport::new(index, Some(order_uid), EntryStatus::Conflict).with_error(
+            "order with the same order_uid already exists",
+            serde_json::to_value(differences).ok(),
+        )
+    }
+}
+
+fn batch_rejected(report: BatchReport) -> AppError {
+    AppError::BatchRejected(serde_json::to_value(report).unwrap_or(Value::Null))
+}
@@ -0,0 +1,350 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::{BuildHasher, RandomState},
+    mem,
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use lru::LruCache;
+use parking_lot::Mutex;
+use tokio::sync::watch;
+
+use crate::{
+    error::AppError,
+    schemas::{Delivery, Item, Order, Payment},
+};
+
+// upper bound of the shard count, small caches get a shard per entry at most
+const MAX_SHARDS: usize = 16;
+
+/// Limits applied to the order cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    pub max_entries: NonZeroUsize,
+    // approximate amount of memory taken by the cached orders
+    pub max_bytes: usize,
+    // entries older than ttl are treated as missing, `None` disables expiration
+    pub ttl: Option<Duration>,
+}
+
+/// Snapshot of the cache counters.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub expirations: u64,
+    pub entries: usize,
+    pub bytes: usize,
+}
+
+struct Entry {
+    order: Order,
+    size: usize,
+    inserted_at: Instant,
+}
+
+struct Shard {
+    lru: LruCache<String, Entry>,
+    bytes: usize,
+    // bumped by every removal, a load that overlaps one may have read the removed data
+    removals: u64,
+}
+
+/// Removals of the shard of an order seen before reading the order from the db.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot(u64);
+
+// result of a load shared with the requests waiting for it
+type Loaded = Option<Result<Order, Arc<AppError>>>;
+
+/// Bounded LRU cache for orders with optional per-entry TTL.
+///
+/// Both the amount of entries and their approximate size in bytes are bounded,
+/// the least recently used orders are evicted first. Orders are spread over
+/// shards with their own locks and limits, so requests for different orders rarely contend.
+/// The locks are never held across an await and are not poisoned by a panic.
+pub struct OrderCache {
+    shards: Box<[Mutex<Shard>]>,
+    hasher: RandomState,
+    // per shard limit
+    max_bytes: usize,
+    ttl: Option<Duration>,
+    // loads in progress, waited for by the concurrent misses of the same order
+    flights: Mutex<HashMap<String, watch::Receiver<Loaded>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl OrderCache {
+    pub fn new(config: CacheConfig) -> OrderCache {
+        let shards = MAX_SHARDS.min(config.max_entries.get());
+        let capacity = NonZeroUsize::new(config.max_entries.get().div_ceil(shards))
+            .expect("shard capacity is positive");
+        OrderCache {
+            shards: (0..shards)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        lru: LruCache::new(capacity),
+                        bytes: 0,
+                        removals: 0,
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+            max_bytes: config.max_bytes / shards,
+            ttl: config.ttl,
+            flights: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, order_uid: &str) -> Option<Order> {
+        let mut shard = self.shard(order_uid).lock();
+
+        let expired = match shard.lru.get(order_uid) {
+            Some(entry) if !self.is_expired(entry) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entry.order.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            if let Some(entry) = shard.lru.pop(order_uid) {
+                shard.bytes -= entry.size;
+            }
+            self.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Returns the cached order or loads it with `load` and caches it.
+    ///
+    /// Concurrent misses of the same order wait for a single load and share its result,
+    /// if the loading request is cancelled one of the waiting ones loads the order instead.
+    pub async fn get_or_load<F, Fut>(&self, order_uid: &str, load: F) -> Result<Order, AppError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Order, AppError>>,
+    {
+        let mut load = Some(load);
+        loop {
+            if let Some(order) = self.get(order_uid) {
+                return Ok(order);
+            }
+
+            // either joins the load in progress or starts a new one
+            let joined = {
+                let mut flights = self.flights.lock();
+                match flights.get(order_uid) {
+                    Some(flight) => Ok(flight.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        flights.insert(order_uid.to_string(), receiver);
+                        Err(sender)
+                    }
+                }
+            };
+            let mut flight = match joined {
+                Ok(flight) => flight,
+                Err(sender) => {
+                    let _landing = Landing {
+                        cache: self,
+                        order_uid,
+                    };
+                    let load = load.take().expect("an order is loaded once per call");
+                    let snapshot = self.snapshot(order_uid);
+                    let result = load().await.map_err(Arc::new);
+                    if let Ok(order) = &result {
+                        self.insert_unless_removed(order.clone(), snapshot);
+                    }
+                    sender.send_replace(Some(result.clone()));
+                    return result.map_err(AppError::Shared);
+                }
+            };
+
+            let loaded = match flight.wait_for(Option::is_some).await {
+                Ok(loaded) => loaded.clone(),
+                // the loading request has been dropped before finishing
+                Err(_) => continue,
+            };
+            if let Some(result) = loaded {
+                return result.map_err(AppError::Shared);
+            }
+        }
+    }
+
+    /// Takes a snapshot to be passed to [`OrderCache::insert_unless_removed`],
+    /// it must be taken before the order is read or written to the db.
+    pub fn snapshot(&self, order_uid: &str) -> Snapshot {
+        Snapshot(self.shard(order_uid).lock().removals)
+    }
+
+    /// Caches the order unless its shard has had a removal since the snapshot,
+    /// the removal may have come after the order was read and made it stale.
+    pub fn insert_unless_removed(&self, order: Order, snapshot: Snapshot) {
+        let size = approx_order_size(&order);
+        // an order that can't fit into the cache at all is not worth evicting everything else
+        if size > self.max_bytes {
+            tracing::debug!(
+                "order {} of ~{} bytes exceeds the cache limit, skipping",
+                order.order_uid,
+                size
+            );
+            return;
+        }
+
+        let mut shard = self.shard(&order.order_uid).lock();
+        if snapshot.0 != shard.removals {
+            tracing::debug!(
+                "order {} may have been removed since it was read, skipping",
+                order.order_uid
+            );
+            return;
+        }
+        let key = order.order_uid.clone();
+        let entry = Entry {
+            order,
+            size,
+            inserted_at: Instant::now(),
+        };
+
+        shard.bytes += size;
+        if let Some((old_key, old_entry)) = shard.lru.push(key.clone(), entry) {
+            shard.bytes -= old_entry.size;
+            // `push` returns either the replaced value for the same key or the evicted lru entry
+            if old_key != key {
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        while shard.bytes > self.max_bytes {
+            match shard.lru.pop_lru() {
+                Some((_, evicted)) => {
+                    shard.bytes -= evicted.size;
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Drops the cached copy of a changed order, it is reloaded from the db on the next request.
+    ///
+    /// Loads of the shard orders in progress are not cached, as they may have read the old data.
+    pub fn remove(&self, order_uid: &str) {
+        let mut shard = self.shard(order_uid).lock();
+        shard.removals += 1;
+        if let Some(entry) = shard.lru.pop(order_uid) {
+            shard.bytes -= entry.size;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let (entries, bytes) = self.shards.iter().fold((0, 0), |(entries, bytes), shard| {
+            let shard = shard.lock();
+            (entries + shard.lru.len(), bytes + shard.bytes)
+        });
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            entries,
+            bytes,
+        }
+    }
+
+    fn shard(&self, order_uid: &str) -> &Mutex<Shard> {
+        let hash = self.hasher.hash_one(order_uid) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+
+    fn is_expired(&self, entry: &Entry) -> bool {
+        match self.ttl {
+            Some(ttl) => entry.inserted_at.elapsed() > ttl,
+            None => false,
+        }
+    }
+}
+
+// Ends the flight of a load once it is over or cancelled.
+struct Landing<'a> {
+    cache: &'a OrderCache,
+    order_uid: &'a str,
+}
+
+impl Drop for Landing<'_> {
+    fn drop(&mut self) {
+        self.cache.flights.lock().remove(self.order_uid);
+    }
+}
+
+// Rough estimation of the heap and inline memory taken by an order,
+// exact accounting is not needed to keep the cache bounded.
+fn approx_order_size(order: &Order) -> usize {
+    let strings = [
+        &order.order_uid,
+        &order.track_number,
+        &order.entry,
+        &order.locale,
+        &order.internal_signature,
+        &order.customer_id,
+        &order.delivery_service,
+        &order.shardkey,
+        &order.oof_shard,
+    ]
+    .iter()
+    .map(|s| s.len())
+    .sum::<usize>();
+
+    // the key is stored separately from the order
+    mem::size_of::<Order>()
+        + mem::size_of::<Entry>()
+        + order.order_uid.len()
+        + strings
+        + approx_delivery_size(&order.delivery)
+        + approx_payment_size(&order.payment)
+        + order.items.iter().map(approx_item_size).sum::<usize>()
+}
+
+fn approx_delivery_size(delivery: &Delivery) -> usize {
+    delivery.name.len()
+        + delivery.phone.len()
+        + delivery.zip.len()
+        + delivery.city.len()
+        + delivery.address.len()
+        + delivery.region.len()
+        + delivery.email.len()
+}
+
+fn approx_payment_size(payment: &Payment) -> usize {
+    payment.transaction.len()
+        + payment.request_id.len()
+        + payment.currency.len()
+        + payment.provider.len()
+        + payment.bank.len()
+}
+
+fn approx_item_size(item: &Item) -> usize {
+    mem::size_of::<Item>()
+        + item.track_number.len()
+        + item.rid.len()
+        + item.name.len()
+        + item.size.len()
+        + item.brand.len()
+}
@@ -0,0 +1,267 @@
+use itertools::Itertools;
+use tokio_postgres::{
+    types::{Json, ToSql},
+    Client, Transaction,
+};
+
+use crate::{
+    error::AppError,
+    metrics,
+    schemas::{Delivery, Item, Order, Payment},
+};
+
+// delivery and payment columns don't clash with the order ones,
+// so the row can be read with the `from_row` of every part
+const COLLECT_ORDER_QUERY: &str = "
+    SELECT
+        o.order_uid, o.track_number, o.entry, o.locale, o.internal_signature, o.customer_id,
+        o.delivery_service, o.shardkey, o.sm_id, o.date_created, o.oof_shard, o.status,
+        d.name, d.phone, d.zip, d.city, d.address, d.region, d.email,
+        p.transaction_id, p.request_id, p.currency, p.provider, p.amount, p.payment_dt,
+        p.bank, p.delivery_cost, p.goods_total, p.custom_fee,
+        COALESCE(
+            (SELECT json_agg(i) FROM items i WHERE i.order_uid = o.order_uid),
+            '[]'::json
+        ) AS items
+    FROM orders o
+    JOIN deliveries d ON d.order_uid = o.order_uid
+    JOIN payments p ON p.order_uid = o.order_uid
+    WHERE o.order_uid = $1";
+
+// the wire protocol sends the number of statement parameters as a signed 16 bit int
+const MAX_QUERY_PARAMS: usize = i16::MAX as usize;
+
+const ORDERS_TARGET: &str = "orders (order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard)";
+const DELIVERIES_TARGET: &str =
+    "deliveries (order_uid, name, phone, zip, city, address, region, email)";
+const ITEMS_TARGET: &str = "items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status)";
+const PAYMENTS_TARGET: &str = "payments (order_uid, transaction_id, request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee)";
+
+/// Stores the order in a single transaction, the idempotency key is stored along with it.
+pub async fn inser_order_tx(
+    conn: &mut Client,
+    order: &Order,
+    idempotency_key: Option<&str>,
+) -> Result<(), AppError> {
+    let transaction = conn.transaction().await?;
+    tracing::debug!("transaction started");
+
+    let result = async move {
+        insert_orders(&transaction, &[order]).await?;
+
+        if let Some(key) = idempotency_key {
+            transaction
+                .execute(
+                    "INSERT INTO idempotency_keys (idempotency_key, order_uid) VALUES ($1, $2)",
+                    &[&key, &order.order_uid],
+                )
+                .await?;
+            tracing::debug!("stored idempotency key");
+        }
+
+        transaction.commit().await?;
+        Ok(())
+    }
+    .await;
+    // a dropped transaction is rolled back
+    metrics::record_transaction(result.is_ok());
+    result
+}
+
+/// Inserts the orders with a multi-row statement per table within the given transaction.
+pub async fn insert_orders(
+    transaction: &Transaction<'_>,
+    orders: &[&Order],
+) -> Result<(), AppError> {
+    let orders_params: Vec<_> = orders
+        .iter()
+        .flat_map(|order| {
+            [
+                &order.order_uid as &(dyn ToSql + Sync),
+                &order.track_number,
+                &order.entry,
+                &order.locale,
+                &order.internal_signature,
+                &order.customer_id,
+                &order.delivery_service,
+                &order.shardkey,
+                &order.sm_id,
+                &order.date_created,
+                &order.oof_shard,
+            ]
+        })
+        .collect();
+    insert_rows(transaction, ORDERS_TARGET, 11, &orders_params).await?;
+
+    tracing::debug!("performed orders insertion");
+
+    let deliveries_params: Vec<_> = orders
+        .iter()
+        .flat_map(|order| {
+            let delivery = &order.delivery;
+            [
+                &order.order_uid as &(dyn ToSql + Sync),
+                &delivery.name,
+                &delivery.phone,
+                &delivery.zip,
+                &delivery.city,
+                &delivery.address,
+                &delivery.region,
+                &delivery.email,
+            ]
+        })
+        .collect();
+    insert_rows(transaction, DELIVERIES_TARGET, 8, &deliveries_params).await?;
+
+    tracing::debug!("performed delivery insertion");
+
+    let items_params: Vec<_> = orders
+        .iter()
+        .flat_map(|order| {
+            order.items.iter().flat_map(|item| {
+                [
+                    &order.order_uid as &(dyn ToSql + Sync),
+                    &item.chrt_id,
+                    &item.track_number,
+                    &item.price,
+                    &item.rid,
+                    &item.name,
+                    &item.sale,
+                    &item.size,
+                    &item.total_price,
+                    &item.nm_id,
+                    &item.brand,
+                    &item.status,
+                ]
+            })
+        })
+        .collect();
+    insert_rows(transaction, ITEMS_TARGET, 12, &items_params).await?;
+
+    tracing::debug!("performed items insertion");
+
+    let payments_params: Vec<_> = orders
+        .iter()
+        .flat_map(|order| {
+            let payment = &order.payment;
+            [
+                &order.order_uid as &(dyn ToSql + Sync),
+                &payment.transaction,
+                &payment.request_id,
+                &payment.currency,
+                &payment.provider,
+                &payment.amount,
+                &payment.payment_dt,
+                &payment.bank,
+                &payment.delivery_cost,
+                &payment.goods_total,
+                &payment.custom_fee,
+            ]
+        })
+        .collect();
+    insert_rows(transaction, PAYMENTS_TARGET, 11, &payments_params).await?;
+
+    tracing::debug!("performed payment insertion");
+
+    // orders are stored with the default `created` status, which starts their history
+    let order_uids: Vec<&str> = orders
+        .iter()
+        .map(|order| order.order_uid.as_str())
+        .collect();
+    transaction
+        .execute(
+            "INSERT INTO order_events (order_uid, to_status) SELECT unnest($1::varchar[]), 'created'",
+            &[&order_uids],
+        )
+        .await?;
+
+    tracing::debug!("performed order events insertion");
+    Ok(())
+}
+
+// Inserts rows of `columns` params each, splitting them into several
+// statements if there are too many params for a single one.
+async fn insert_rows(
+    transaction: &Transaction<'_>,
+    target: &str,
+    columns: usize,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<(), AppError> {
+    let rows_per_statement = MAX_QUERY_PARAMS / columns;
+    for chunk in params.chunks(rows_per_statement * columns) {
+        let query = format!(
+            "INSERT INTO {target} VALUES {}",
+            values_placeholders(chunk.len() / columns, columns)
+        );
+        transaction.execute(query.as_str(), chunk).await?;
+    }
+    Ok(())
+}
+
+/// Generates placeholders of a multi-row insert, e.g. `($1, $2), ($3, $4)` for 2 rows of 2 columns.
+pub fn values_placeholders(rows: usize, columns: usize) -> String {
+    (1..=rows * columns)
+        .chunks(columns)
+        .into_iter()
+        .map(|row| format!("({})", row.map(|i| format!("${i}")).join(", ")))
+        .join(", ")
+}
+
+/// Collects the order with all its parts in a single round-trip.
+pub async fn collect_order(conn: &Client, order_uid: &str) -> Result<Order, AppError> {
+    let row = conn
+        .query_opt(COLLECT_ORDER_QUERY, &[&order_uid])
+        .await?
+        .ok_or_else(order_not_found)?;
+
+    let Json(items): Json<Vec<Item>> = row.try_get("items")?;
+    Ok(Order::from_row(
+        &row,
+        Delivery::from_row(&row),
+        Payment::from_row(&row),
+        items,
+    ))
+}
+
+/// Collects the order with a query per table.
+///
+/// Kept as a baseline for the `collect_order` benchmark.
+pub async fn collect_order_sequential(conn: &Client, order_uid: &str) -> Result<Order, AppError> {
+    let order_row = conn
+        .query_opt("SELECT * FROM orders WHERE order_uid = $1", &[&order_uid])
+        .await?
+        .ok_or_else(order_not_found)?;
+
+    // request items
+    let items_rows = conn
+        .query("SELECT * FROM items WHERE order_uid = $1", &[&order_uid])
+        .await?;
+
+    let items = items_rows
+        .into_iter()
+        .map(|row| Item::from_row(&row))
+        .collect::<Vec<_>>();
+
+    // request delivery
+    let delivery_row = conn
+        .query_opt(
+            "SELECT * FROM deliveries WHERE order_uid = $1",
+            &[&order_uid],
+        )
+        .await?
+        .ok_or_else(order_not_found)?;
+    let delivery = Delivery::from_row(&delivery_row);
+
+    // request payment
+    let payment_row = conn
+        .query_opt("SELECT * FROM payments WHERE order_uid = $1", &[&order_uid])
+        .await?
+        .ok_or_else(order_not_found)?;
+    let payment = Payment::from_row(&payment_row);
+
+    Ok(Order::from_row(&order_row, delivery, payment, items))
+}
+
+pub(crate) fn order_not_found() -> AppError {
+    AppError::NotFound("The order has not been found in the sistem".to_string())
+}
@@ -0,0 +1,241 @@
+use std::{fmt, sync::Arc};
+
+use axum::{
+    extract::rejection::{JsonRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bb8::RunError;
+use serde::Serialize;
+use serde_json::Value;
+use tokio_postgres::error::SqlState;
+use utoipa::ToSchema;
+
+use crate::{request_id, validation::FieldError};
+
+/// Errors returned by the service handlers.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound(String),
+    // request body is not a valid JSON of the expected shape
+    InvalidBody(JsonRejection),
+    BadRequest(String),
+    // missing, unknown or revoked API key
+    Unauthorized(String),
+    // the caller role is not allowed to do the request
+    Forbidden(String),
+    Validation(Vec<FieldError>),
+    Conflict {
+        message: String,
+        details: Option<Value>,
+    },
+    // order or idempotency key is already stored, raised by the repositories without unique constraints
+    AlreadyExists(String),
+    // atomic batch is not stored, details hold the per-order report
+    BatchRejected(Value),
+    // the API key has run out of requests, retry is possible in `retry_after` seconds
+    RateLimited {
+        retry_after: u64,
+    },
+    // no db connection became available in time
+    PoolTimeout,
+    Database(tokio_postgres::Error),
+    // error of a load shared by the concurrent requests for the same order
+    Shared(Arc<AppError>),
+}
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorBody<'a> {
+    error: ErrorContent<'a>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(crate) struct ErrorContent<'a> {
+    code: &'static str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<Value>,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::InvalidBody(rejection) => rejection.status(),
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::AlreadyExists(_) => StatusCode::CONFLICT,
+            AppError::BatchRejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::PoolTimeout => StatusCode::SERVICE_UNAVAILABLE,
+            // lost connection is a temporary condition unlike a failed query
+            AppError::Database(e) if e.is_closed() => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Shared(e) => e.status(),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::InvalidBody(_) => "invalid_body",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Validation(_) => "validation_failed",
+            AppError::Conflict { .. } => "conflict",
+            AppError::AlreadyExists(_) => "already_exists",
+            AppError::BatchRejected(_) => "batch_rejected",
+            AppError::RateLimited { .. } => "rate_limited",
+            AppError::PoolTimeout => "pool_timeout",
+            AppError::Database(_) => "database_error",
+            AppError::Shared(e) => e.code(),
+        }
+    }
+
+    /// Checks whether the error is caused by a duplicate key in the storage.
+    pub fn is_unique_violation(&self) -> bool {
+        match self {
+            AppError::Database(e) => e.code() == Some(&SqlState::UNIQUE_VIOLATION),
+            AppError::AlreadyExists(_) => true,
+            AppError::Shared(e) => e.is_unique_violation(),
+            _ => false,
+        }
+    }
+
+    /// Checks whether the same request may succeed later, e.g. once the db is reachable again.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            AppError::PoolTimeout => true,
+            // data exceptions and integrity violations repeat on every attempt
+            AppError::Database(e) => !e
+                .code()
+                .is_some_and(|code| code.code().starts_with("22") || code.code().starts_with("23")),
+            AppError::Shared(e) => e.is_transient(),
+            _ => false,
+        }
+    }
+
+    // db errors may contain internals of the schema, so clients get a generic message
+    pub fn public_message(&self) -> String {
+        match self {
+            AppError::Database(_) => "Database error".to_string(),
+            AppError::Shared(e) => e.public_message(),
+            e => e.to_string(),
+        }
+    }
+
+    fn details(self) -> Option<Value> {
+        match self {
+            AppError::Validation(errors) => serde_json::to_value(errors).ok(),
+            AppError::Conflict { details, .. } => details,
+            AppError::BatchRejected(report) => Some(report),
+            AppError::Shared(e) => Arc::try_unwrap(e).ok().and_then(AppError::details),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(message) => write!(f, "{}", message),
+            AppError::InvalidBody(rejection) => write!(f, "{}", rejection.body_text()),
+            AppError::BadRequest(message) => write!(f, "{}", message),
+            AppError::Unauthorized(message) => write!(f, "{}", message),
+            AppError::Forbidden(message) => write!(f, "{}", message),
+            AppError::Validation(errors) => {
+                write!(f, "Order validation failed for {} fields", errors.len())
+            }
+            AppError::Conflict { message, .. } => write!(f, "{}", message),
+            AppError::AlreadyExists(message) => write!(f, "{}", message),
+            AppError::BatchRejected(_) => write!(f, "Batch has been rejected as a whole"),
+            AppError::RateLimited { retry_after } => {
+                write!(f, "Rate limit exceeded, retry in {} seconds", retry_after)
+            }
+            AppError::PoolTimeout => write!(f, "Timed out waiting for a database connection"),
+            AppError::Database(e) => write!(f, "Database error: {}", e),
+            AppError::Shared(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<tokio_postgres::Error> for AppError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<RunError<tokio_postgres::Error>> for AppError {
+    fn from(err: RunError<tokio_postgres::Error>) -> Self {
+        match err {
+            RunError::User(e) => AppError::Database(e),
+            RunError::TimedOut => AppError::PoolTimeout,
+        }
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        AppError::InvalidBody(rejection)
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        AppError::BadRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        let request_id = request_id::current();
+        if status.is_server_error() {
+            tracing::error!("request failed: {}", self);
+        } else {
+            tracing::debug!("request rejected: {}", self);
+        }
+
+        let message = self.public_message();
+        let code = self.code();
+        let retry = match &self {
+            AppError::Shared(e) if matches!(**e, AppError::PoolTimeout) => Some(1),
+            AppError::PoolTimeout => Some(1),
+            AppError::RateLimited { retry_after } => Some(*retry_after),
+            _ => None,
+        };
+        let unauthorized = matches!(self, AppError::Unauthorized(_));
+        let body = ErrorBody {
+            error: ErrorContent {
+                code,
+                message: &message,
+                request_id,
+                details: self.details(),
+            },
+        };
+
+        let mut response = (status, Json(body)).into_response();
+        if let Some(seconds) = retry {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        }
+        if unauthorized {
+            response.headers_mut().insert(
+                header::WWW_AUTHENTICATE,
+                HeaderValue::from_static("Bearer realm=\"view-service\""),
+            );
+        }
+        response
+    }
+}
@@ -0,0 +1,523 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{
+        rejection::{JsonRejection, QueryRejection},
+        Json, Path, Query, State,
+    },
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    batch::{self, BatchParams, BatchReport},
+    error::{AppError, ErrorBody},
+    idempotency::{self, Existing},
+    listing::{OrderFilter, OrderPage},
+    pii::{self, Erasure, ErasureRequest, Role},
+    schemas::{self, Order},
+    stats::{
+        BrandStats, DailyRevenue, DeliveryServiceStats, ProductStats, RegionDeliveryCost, Stats,
+        StatsParams,
+    },
+    status::{OrderHistory, OrderStatus, StatusChange, StatusUpdate},
+    validation, AppState,
+};
+
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+// process order post
+#[utoipa::path(
+    post,
+    path = "/order",
+    tag = "orders",
+    summary = "Create an order",
+    description = "Requires the `write` scope. The received status is ignored, new orders are `created`.",
+    request_body = Order,
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retries with the same key return the stored order"),
+        ("X-Role" = Option<Role>, Header, description = "Role used when no API key is required"),
+    ),
+    responses(
+        (status = 201, description = "Order is stored", body = String, content_type = "text/plain"),
+        (status = 200, description = "Identical order is already stored", body = Order),
+        (status = 400, description = "Invalid Idempotency-Key header", body = ErrorBody),
+        (status = 409, description = "Another order has the same order_uid or Idempotency-Key", body = ErrorBody),
+        (status = 422, description = "Order is not valid", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn create_order(
+    State(state): State<Arc<AppState>>,
+    role: Role,
+    headers: HeaderMap,
+    payload: Result<Json<schemas::Order>, JsonRejection>,
+) -> Result<Response, AppError> {
+    tracing::debug!("order post request");
+    let Json(mut order) = payload?;
+    validation::validate_order(&order).map_err(AppError::Validation)?;
+    // orders are stored as created whatever status is received
+    order.status = OrderStatus::Created;
+
+    let idempotency_key = match headers.get(IDEMPOTENCY_KEY_HEADER).map(|v| v.to_str()) {
+        Some(Ok(key)) if !key.is_empty() => Some(key.to_string()),
+        Some(_) => {
+            return Err(AppError::BadRequest(
+                "Idempotency-Key header must be a non-empty string".to_string(),
+            ))
+        }
+        None => None,
+    };
+
+    // resubmissions are answered without an insert, the stored order is read from the cache or the db
+    if let Some(response) =
+        resolve_resubmission(&order, idempotency_key.as_deref(), role, &state).await?
+    {
+        return Ok(response);
+    }
+
+    // a delete committed right after the insert must not leave the order cached
+    let snapshot = state.cache.snapshot(&order.order_uid);
+    match state.repo.insert(&order, idempotency_key.as_deref()).await {
+        Ok(_) => {
+            state.cache.insert_unless_removed(order, snapshot);
+            tracing::debug!("transaction commited");
+            Ok((
+                StatusCode::CREATED,
+                "Order successfully created".to_string(),
+            )
+                .into_response())
+        }
+        // a concurrent request has stored the same order or used the same key first
+        Err(e) if e.is_unique_violation() => {
+            tracing::debug!("transaction reverted: {}", e);
+            match resolve_resubmission(&order, idempotency_key.as_deref(), role, &state).await? {
+                Some(response) => Ok(response),
+                None => Err(AppError::Conflict {
+                    message: "Order has been concurrently modified".to_string(),
+                    details: None,
+                }),
+            }
+        }
+        Err(e) => {
+            tracing::debug!("transaction reverted");
+            Err(e)
+        }
+    }
+}
+
+// Builds a response for an order that has already been submitted either
+// with the same idempotency key or with the same order_uid.
+async fn resolve_resubmission(
+    order: &Order,
+    idempotency_key: Option<&str>,
+    role: Role,
+    state: &Arc<AppState>,
+) -> Result<Option<Response>, AppError> {
+    if let Some(key) = idempotency_key {
+        if let Some(order_uid) = idempotency::find_key_order(key, state).await? {
+            if order_uid != order.order_uid {
+                return Err(AppError::Conflict {
+                    message: "Idempotency-Key has already been used for another order".to_string(),
+                    details: Some(serde_json::json!({ "order_uid": order_uid })),
+                });
+            }
+        }
+    }
+
+    match idempotency::find_existing(order, state).await? {
+        Existing::None => Ok(None),
+        Existing::Identical(existing) => {
+            tracing::debug!("order {} is resubmitted", existing.order_uid);
+            let existing = pii::redact(*existing, role);
+            Ok(Some((StatusCode::OK, Json(existing)).into_response()))
+        }
+        Existing::Conflicting(differences) => Err(AppError::Conflict {
+            message: "Order with the same order_uid already exists".to_string(),
+            details: Some(serde_json::json!({
+                "order_uid": order.order_uid,
+                "differences": pii::redact_diff(differences, role),
+            })),
+        }),
+    }
+}
+
+// process order get
+#[utoipa::path(
+    get,
+    path = "/order/{order_uid}",
+    tag = "orders",
+    summary = "Get an order",
+    description = "Requires the `read` scope. Delivery PII is masked unless the caller is an admin.",
+    params(
+        ("order_uid" = String, Path),
+        ("X-Role" = Option<Role>, Header, description = "Role used when no API key is required"),
+    ),
+    responses(
+        (status = 200, body = Order),
+        (status = 404, description = "Order is not found", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn get_order(
+    Path(order_uid): Path<String>,
+    State(state): State<Arc<AppState>>,
+    role: Role,
+) -> Result<Response, AppError> {
+    let order = find_order(&state, &order_uid).await?;
+    Ok((StatusCode::OK, Json(pii::redact(order, role))).into_response())
+}
+
+/// Looks the order up in the cache first, a missing order is loaded from the db and cached.
+///
+/// Concurrent requests for the same missing order share a single db query.
+pub async fn find_order(state: &AppState, order_uid: &str) -> Result<Order, AppError> {
+    tracing::debug!("checking cache for order with uid: {}", order_uid);
+    state
+        .cache
+        .get_or_load(order_uid, || async {
+            let stats = state.cache.stats();
+            tracing::debug!(
+                hits = stats.hits,
+                misses = stats.misses,
+                evictions = stats.evictions,
+                expirations = stats.expirations,
+                entries = stats.entries,
+                bytes = stats.bytes,
+                "no cahce hit"
+            );
+            state.repo.get(order_uid).await
+        })
+        .await
+}
+
+// process order removal
+#[utoipa::path(
+    delete,
+    path = "/order/{order_uid}",
+    tag = "orders",
+    summary = "Delete an order",
+    description = "Requires the `write` scope.",
+    params(("order_uid" = String, Path)),
+    responses(
+        (status = 204, description = "Order is deleted"),
+        (status = 404, description = "Order is not found", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn delete_order(
+    Path(order_uid): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    tracing::debug!("deleting order {}", order_uid);
+    state.repo.delete(&order_uid).await?;
+    state.cache.remove(&order_uid);
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+// process order status change
+#[utoipa::path(
+    patch,
+    path = "/order/{order_uid}/status",
+    tag = "orders",
+    summary = "Change the order status",
+    description = "Requires the `write` scope. Delivered and cancelled orders can't be changed.",
+    params(("order_uid" = String, Path)),
+    request_body = StatusChange,
+    responses(
+        (status = 200, body = StatusUpdate),
+        (status = 404, description = "Order is not found", body = ErrorBody),
+        (status = 409, description = "Status can't be reached from the current one", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn update_order_status(
+    Path(order_uid): Path<String>,
+    State(state): State<Arc<AppState>>,
+    payload: Result<Json<StatusChange>, JsonRejection>,
+) -> Result<Response, AppError> {
+    let Json(change) = payload?;
+    tracing::debug!(
+        "changing status of order {} to {}",
+        order_uid,
+        change.status
+    );
+
+    let event = state.repo.change_status(&order_uid, &change).await?;
+    // dropping the entry keeps the cache consistent with concurrent changes applied in any order
+    state.cache.remove(&order_uid);
+
+    let update = StatusUpdate {
+        order_uid,
+        status: event.to_status,
+        event,
+    };
+    Ok((StatusCode::OK, Json(update)).into_response())
+}
+
+// process customer erasure, the delivery PII of all the customer orders is anonymized
+#[utoipa::path(
+    post,
+    path = "/customers/{customer_id}/erasure",
+    tag = "customers",
+    summary = "Erase the delivery data of a customer",
+    description = "Requires the `admin` scope. The erasure is recorded in an append-only audit trail.",
+    params(
+        ("customer_id" = String, Path),
+        ("X-Role" = Option<Role>, Header, description = "Role used when no API key is required"),
+    ),
+    request_body = ErasureRequest,
+    responses(
+        (status = 200, body = Erasure),
+        (status = 400, description = "Reason is empty", body = ErrorBody),
+        (status = 403, description = "Caller is not an admin", body = ErrorBody),
+        (status = 404, description = "Customer has no orders", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn erase_customer(
+    Path(customer_id): Path<String>,
+    State(state): State<Arc<AppState>>,
+    role: Role,
+    payload: Result<Json<ErasureRequest>, JsonRejection>,
+) -> Result<Response, AppError> {
+    if !role.can_erase() {
+        return Err(AppError::Forbidden(format!(
+            "Role {role} is not allowed to erase customers"
+        )));
+    }
+    let Json(request) = payload?;
+    if request.reason.trim().is_empty() {
+        return Err(AppError::BadRequest(
+            "Erasure reason must not be empty".to_string(),
+        ));
+    }
+    tracing::info!("erasing the delivery data of customer {}", customer_id);
+
+    let erasure = state
+        .repo
+        .erase_customer(&customer_id, &request, role)
+        .await?;
+    for order_uid in &erasure.order_uids {
+        state.cache.remove(order_uid);
+    }
+    Ok((StatusCode::OK, Json(erasure)).into_response())
+}
+
+// process order history request
+#[utoipa::path(
+    get,
+    path = "/order/{order_uid}/history",
+    tag = "orders",
+    summary = "Get the status history of an order",
+    description = "Requires the `read` scope.",
+    params(("order_uid" = String, Path)),
+    responses(
+        (status = 200, body = OrderHistory),
+        (status = 404, description = "Order is not found", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn order_history(
+    Path(order_uid): Path<String>,
+    State(state): State<Arc<AppState>>,
+) -> Result<Response, AppError> {
+    let history = state.repo.history(&order_uid).await?;
+    Ok((StatusCode::OK, Json(history)).into_response())
+}
+
+// process order search
+#[utoipa::path(
+    get,
+    path = "/orders",
+    tag = "orders",
+    summary = "Search orders",
+    description = "Requires the `read` scope. Orders are sorted by `date_created`, newest first.",
+    params(OrderFilter),
+    responses(
+        (status = 200, body = OrderPage),
+        (status = 400, description = "Invalid filter, limit or cursor", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn list_orders(
+    State(state): State<Arc<AppState>>,
+    filter: Result<Query<OrderFilter>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(filter) = filter?;
+    tracing::debug!("order search request with {:?}", filter);
+
+    let page = state.repo.list(filter).await?;
+    Ok((StatusCode::OK, Json(page)).into_response())
+}
+
+// process stats requests, all of them take the same range parameters
+#[utoipa::path(
+    get,
+    path = "/stats/revenue",
+    tag = "stats",
+    summary = "Daily revenue per currency",
+    description = "Requires the `read` scope.",
+    params(StatsParams),
+    responses(
+        (status = 200, body = Stats<DailyRevenue>),
+        (status = 400, description = "Invalid range or limit, or the amounts overflow", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn revenue_stats(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let params = stats_params(params)?;
+    let rows = state.repo.revenue(&params).await?;
+    Ok((StatusCode::OK, Json(Stats::new(&params, rows))).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/brands",
+    tag = "stats",
+    summary = "Top brands",
+    description = "Requires the `read` scope.",
+    params(StatsParams),
+    responses(
+        (status = 200, body = Stats<BrandStats>),
+        (status = 400, description = "Invalid range or limit, or the amounts overflow", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn brand_stats(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let params = stats_params(params)?;
+    let rows = state.repo.top_brands(&params).await?;
+    Ok((StatusCode::OK, Json(Stats::new(&params, rows))).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/products",
+    tag = "stats",
+    summary = "Top products",
+    description = "Requires the `read` scope.",
+    params(StatsParams),
+    responses(
+        (status = 200, body = Stats<ProductStats>),
+        (status = 400, description = "Invalid range or limit, or the amounts overflow", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn product_stats(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let params = stats_params(params)?;
+    let rows = state.repo.top_products(&params).await?;
+    Ok((StatusCode::OK, Json(Stats::new(&params, rows))).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/delivery-costs",
+    tag = "stats",
+    summary = "Average delivery cost per region",
+    description = "Requires the `read` scope.",
+    params(StatsParams),
+    responses(
+        (status = 200, body = Stats<RegionDeliveryCost>),
+        (status = 400, description = "Invalid range or limit, or the amounts overflow", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn delivery_cost_stats(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let params = stats_params(params)?;
+    let rows = state.repo.delivery_costs(&params).await?;
+    Ok((StatusCode::OK, Json(Stats::new(&params, rows))).into_response())
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/delivery-services",
+    tag = "stats",
+    summary = "Orders per delivery service",
+    description = "Requires the `read` scope.",
+    params(StatsParams),
+    responses(
+        (status = 200, body = Stats<DeliveryServiceStats>),
+        (status = 400, description = "Invalid range or limit", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn delivery_service_stats(
+    State(state): State<Arc<AppState>>,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let params = stats_params(params)?;
+    let rows = state.repo.delivery_services(&params).await?;
+    Ok((StatusCode::OK, Json(Stats::new(&params, rows))).into_response())
+}
+
+fn stats_params(
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<StatsParams, AppError> {
+    let Query(params) = params?;
+    tracing::debug!("stats request with {:?}", params);
+    if let (Some(from), Some(to)) = (params.created_from, params.created_to) {
+        if from > to {
+            return Err(AppError::BadRequest(
+                "created_from must not be later than created_to".to_string(),
+            ));
+        }
+    }
+    Ok(params)
+}
+
+// process batch order post, the body is a JSON array or NDJSON
+#[utoipa::path(
+    post,
+    path = "/orders/batch",
+    tag = "orders",
+    summary = "Create orders in a batch",
+    description = "Requires the `write` scope.",
+    params(BatchParams),
+    request_body(content(
+        (Vec<Order> = "application/json"),
+        (String = "application/x-ndjson"),
+    )),
+    responses(
+        (status = 200, description = "Per-order report", body = BatchReport),
+        (status = 400, description = "Body is neither a JSON array nor NDJSON", body = ErrorBody),
+        (status = 422, description = "Atomic batch is rejected, the report is in the details", body = ErrorBody),
+    ),
+    security(("api_key" = []), ("bearer" = [])),
+)]
+pub async fn create_orders_batch(
+    State(state): State<Arc<AppState>>,
+    role: Role,
+    headers: HeaderMap,
+    params: Result<Query<BatchParams>, QueryRejection>,
+    body: Bytes,
+) -> Result<Response, AppError> {
+    let Query(params) = params?;
+    let ndjson = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.starts_with("application/x-ndjson") || value.starts_with("application/ndjson")
+        });
+
+    let entries = batch::parse_batch(&body, ndjson)?;
+    tracing::debug!(
+        "batch of {} orders in {:?} mode",
+        entries.len(),
+        params.mode
+    );
+    let report = batch::ingest_batch(&state, entries, params.mode, role).await?;
+    Ok((StatusCode::OK, Json(report)).into_response())
+}
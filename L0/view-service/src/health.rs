@@ -0,0 +1,147 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{migrations, warmup::WarmupStatus, AppState};
+
+// probes are expected to be answered fast, unlike requests waiting for the pool
+const DB_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DbStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct DbCheck {
+    pub status: DbStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct MigrationsCheck {
+    // `None` if the db is unreachable or has no migrations applied
+    pub current: Option<i64>,
+    pub expected: Option<i64>,
+    pub up_to_date: bool,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Checks {
+    pub database: DbCheck,
+    pub migrations: MigrationsCheck,
+    pub cache_warmup: WarmupStatus,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    // the first failed check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub checks: Checks,
+}
+
+/// Liveness probe, the process is up as long as it answers.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "probes",
+    responses((status = 200, description = "Process is up", body = Object, example = json!({"status": "ok"}))),
+)]
+pub async fn healthz() -> Response {
+    (StatusCode::OK, Json(serde_json::json!({"status": "ok"}))).into_response()
+}
+
+/// Readiness probe, returns `503 Service Unavailable` unless the service is able to serve orders.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "probes",
+    responses(
+        (status = 200, description = "Service is ready", body = Readiness),
+        (status = 503, description = "Service is not ready", body = Readiness),
+    ),
+)]
+pub async fn readyz(State(state): State<Arc<AppState>>) -> Response {
+    let readiness = check_readiness(&state).await;
+    let status = if readiness.ready {
+        StatusCode::OK
+    } else {
+        tracing::debug!("service is not ready: {:?}", readiness.reason);
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(readiness)).into_response()
+}
+
+pub async fn check_readiness(state: &AppState) -> Readiness {
+    let expected = migrations::latest_version();
+    let (database, current) = match tokio::time::timeout(DB_CHECK_TIMEOUT, probe_db(state)).await {
+        Ok(Ok(current)) => (
+            DbCheck {
+                status: DbStatus::Up,
+                error: None,
+            },
+            current,
+        ),
+        Ok(Err(error)) => (
+            DbCheck {
+                status: DbStatus::Down,
+                error: Some(error),
+            },
+            None,
+        ),
+        Err(_) => (
+            DbCheck {
+                status: DbStatus::Down,
+                error: Some("timed out".to_string()),
+            },
+            None,
+        ),
+    };
+    let migrations = MigrationsCheck {
+        current,
+        expected,
+        up_to_date: current >= expected,
+    };
+    let cache_warmup = state.warmup.get();
+
+    let reason = if let Some(error) = &database.error {
+        Some(format!("database is unreachable: {}", error))
+    } else if !migrations.up_to_date {
+        Some("database schema is not up to date".to_string())
+    } else if !cache_warmup.is_finished() {
+        Some("cache warm-up is in progress".to_string())
+    } else {
+        None
+    };
+
+    Readiness {
+        ready: reason.is_none(),
+        reason,
+        checks: Checks {
+            database,
+            migrations,
+            cache_warmup,
+        },
+    }
+}
+
+// runs a cheap query on the storage and returns the applied migration version,
+// the probe is open, so the error details such as host names only go to the log
+async fn probe_db(state: &AppState) -> Result<Option<i64>, String> {
+    state.repo.schema_version().await.map_err(|e| {
+        tracing::warn!("readiness check of the database failed: {}", e);
+        e.public_message()
+    })
+}
@@ -0,0 +1,104 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{error::AppError, pii, schemas::Order, status::OrderStatus, AppState};
+
+/// Single field that differs between a stored order and a resubmitted one.
+#[derive(Serialize, Debug, Clone)]
+pub struct FieldDiff {
+    pub field: String,
+    pub existing: Value,
+    pub received: Value,
+}
+
+/// State of an order with the same `order_uid` as the received one.
+pub enum Existing {
+    None,
+    // resubmission of exactly the same order
+    Identical(Box<Order>),
+    Conflicting(Vec<FieldDiff>),
+}
+
+/// Looks for an already stored order with the same uid and compares it to the received one.
+pub async fn find_existing(order: &Order, state: &Arc<AppState>) -> Result<Existing, AppError> {
+    let existing = match state.cache.get(&order.order_uid) {
+        Some(existing) => existing,
+        None => match state.repo.get(&order.order_uid).await {
+            Ok(existing) => existing,
+            Err(AppError::NotFound(_)) => return Ok(Existing::None),
+            Err(e) => return Err(e),
+        },
+    };
+
+    let diff = order_diff(&existing, order);
+    if diff.is_empty() {
+        Ok(Existing::Identical(Box::new(existing)))
+    } else {
+        Ok(Existing::Conflicting(diff))
+    }
+}
+
+/// Returns the uid of the order created with the idempotency key, if any.
+pub async fn find_key_order(key: &str, state: &Arc<AppState>) -> Result<Option<String>, AppError> {
+    state.repo.key_order(key).await
+}
+
+/// Lists all the fields that differ between two orders.
+///
+/// Items are stored without any particular order, so they are compared sorted.
+/// The stored delivery PII is masked.
+pub fn order_diff(existing: &Order, received: &Order) -> Vec<FieldDiff> {
+    let mut diff = Vec::new();
+    diff_values(
+        "",
+        &normalized_json(existing),
+        &normalized_json(received),
+        &mut diff,
+    );
+    pii::mask_diff(&mut diff);
+    diff
+}
+
+fn normalized_json(order: &Order) -> Value {
+    let mut order = order.clone();
+    // the stored order may have moved on since it was received
+    order.status = OrderStatus::default();
+    order
+        .items
+        .sort_by(|a, b| (a.chrt_id, &a.rid).cmp(&(b.chrt_id, &b.rid)));
+    serde_json::to_value(order).unwrap_or(Value::Null)
+}
+
+fn diff_values(path: &str, existing: &Value, received: &Value, diff: &mut Vec<FieldDiff>) {
+    match (existing, received) {
+        (Value::Object(a), Value::Object(b)) => {
+            let keys = a.keys().chain(b.keys().filter(|key| !a.contains_key(*key)));
+            for key in keys {
+                let field = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff_values(
+                    &field,
+                    a.get(key).unwrap_or(&Value::Null),
+                    b.get(key).unwrap_or(&Value::Null),
+                    diff,
+                );
+            }
+        }
+        (Value::Array(a), Value::Array(b)) if a.len() == b.len() => {
+            for (i, (a, b)) in a.iter().zip(b).enumerate() {
+                diff_values(&format!("{path}[{i}]"), a, b, diff);
+            }
+        }
+        (a, b) if a != b => diff.push(FieldDiff {
+            field: path.to_string(),
+            existing: a.clone(),
+            received: b.clone(),
+        }),
+        _ => {}
+    }
+}
@@ -0,0 +1,89 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use serde::Serialize;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+use super::IngestError;
+
+/// Destination for messages that can't be turned into stored orders.
+#[async_trait]
+pub trait DeadLetterSink: Send + Sync {
+    async fn send(&self, payload: &[u8], reason: &str) -> Result<(), IngestError>;
+}
+
+#[derive(Serialize)]
+struct DeadLetterRecord<'a> {
+    failed_at: String,
+    reason: &'a str,
+    payload: String,
+}
+
+/// Appends dead letters as JSON lines to a local file.
+pub struct FileDeadLetterSink {
+    file: Mutex<File>,
+}
+
+impl FileDeadLetterSink {
+    pub async fn open(path: &Path) -> Result<FileDeadLetterSink, IngestError> {
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(FileDeadLetterSink {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for FileDeadLetterSink {
+    async fn send(&self, payload: &[u8], reason: &str) -> Result<(), IngestError> {
+        let record = DeadLetterRecord {
+            failed_at: Utc::now().to_rfc3339(),
+            reason,
+            payload: String::from_utf8_lossy(payload).into_owned(),
+        };
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        file.write_all(&line).await?;
+        file.flush().await?;
+        Ok(())
+    }
+}
+
+/// Publishes dead letters to a NATS subject with the failure reason in a header.
+pub struct NatsDeadLetterSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+impl NatsDeadLetterSink {
+    pub async fn connect(url: &str, subject: String) -> Result<NatsDeadLetterSink, IngestError> {
+        let client = async_nats::connect(url).await?;
+        Ok(NatsDeadLetterSink { client, subject })
+    }
+}
+
+#[async_trait]
+impl DeadLetterSink for NatsDeadLetterSink {
+    async fn send(&self, payload: &[u8], reason: &str) -> Result<(), IngestError> {
+        let mut headers = async_nats::HeaderMap::new();
+        // header values can't span multiple lines
+        headers.insert(
+            "Dead-Letter-Reason",
+            reason.replace(['\r', '\n'], " ").as_str(),
+        );
+
+        self.client
+            .publish_with_headers(self.subject.clone(), headers, payload.to_vec().into())
+            .await?;
+        // make sure the dead letter is sent before the original message is acknowledged
+        self.client.flush().await?;
+        Ok(())
+    }
+}
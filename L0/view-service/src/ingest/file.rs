@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use async_trait::async_trait;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, Lines};
+
+use super::{IngestError, OrderSource, SourceMessage};
+
+/// Source reading one JSON order per line, mostly useful for local testing.
+pub struct FileSource {
+    name: String,
+    lines: Lines<BufReader<Box<dyn AsyncRead + Send + Unpin>>>,
+    // reading is not resumed after an io error
+    failed: bool,
+}
+
+impl FileSource {
+    /// Opens the file at `path`, `-` stands for stdin.
+    pub async fn open(path: &Path) -> Result<FileSource, IngestError> {
+        let reader: Box<dyn AsyncRead + Send + Unpin> = if path == Path::new("-") {
+            Box::new(tokio::io::stdin())
+        } else {
+            Box::new(tokio::fs::File::open(path).await?)
+        };
+
+        Ok(FileSource {
+            name: format!("file {}", path.display()),
+            lines: BufReader::new(reader).lines(),
+            failed: false,
+        })
+    }
+}
+
+#[async_trait]
+impl OrderSource for FileSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn next_message(&mut self) -> Option<Result<SourceMessage, IngestError>> {
+        if self.failed {
+            return None;
+        }
+        loop {
+            match self.lines.next_line().await {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => return Some(Ok(SourceMessage::new(line.into_bytes(), None))),
+                Ok(None) => return None,
+                Err(e) => {
+                    self.failed = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use clap::ValueEnum;
+use itertools::Itertools;
+use serde::Deserialize;
+
+use crate::{
+    idempotency::{self, Existing},
+    schemas::Order,
+    shutdown::Shutdown,
+    status::OrderStatus,
+    validation::validate_order,
+    AppState,
+};
+
+pub mod dead_letter;
+pub mod file;
+pub mod nats;
+
+pub use dead_letter::{DeadLetterSink, FileDeadLetterSink, NatsDeadLetterSink};
+pub use file::FileSource;
+pub use nats::{NatsConfig, NatsSource};
+
+pub type IngestError = Box<dyn std::error::Error + Send + Sync>;
+
+// a message that can't be stored after all the attempts is redelivered by the source
+// if the failure is transient, e.g. the db is down, and dead-lettered otherwise
+const MAX_INSERT_ATTEMPTS: u32 = 5;
+const INSERT_RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const REDELIVERY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(ValueEnum, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    // orders are accepted only through the HTTP API
+    #[default]
+    None,
+    // JSON lines from a file or stdin
+    File,
+    // NATS JetStream durable consumer
+    Nats,
+}
+
+/// Confirms to the source that a message has been processed
+/// and should not be delivered again.
+#[async_trait]
+pub trait Acker: Send {
+    async fn ack(self: Box<Self>) -> Result<(), IngestError>;
+
+    /// Asks the source to deliver the message again after `delay`.
+    async fn nack(self: Box<Self>, delay: Duration) -> Result<(), IngestError>;
+}
+
+/// Raw order payload received from a source.
+pub struct SourceMessage {
+    pub payload: Vec<u8>,
+    // sources without delivery guarantees have nothing to acknowledge
+    acker: Option<Box<dyn Acker>>,
+}
+
+impl SourceMessage {
+    pub fn new(payload: Vec<u8>, acker: Option<Box<dyn Acker>>) -> SourceMessage {
+        SourceMessage { payload, acker }
+    }
+
+    pub async fn ack(self) -> Result<(), IngestError> {
+        match self.acker {
+            Some(acker) => acker.ack().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the message to the source, `false` if the source can't deliver it again.
+    pub async fn nack(self, delay: Duration) -> Result<bool, IngestError> {
+        match self.acker {
+            Some(acker) => acker.nack(delay).await.map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
+
+/// Stream of orders coming from outside of the HTTP API.
+#[async_trait]
+pub trait OrderSource: Send {
+    fn name(&self) -> &str;
+
+    /// Waits for the next message, `None` means that the source is exhausted.
+    async fn next_message(&mut self) -> Option<Result<SourceMessage, IngestError>>;
+}
+
+/// Reads orders from the source until it is exhausted or the shutdown is triggered
+/// and stores them in the db.
+///
+/// A message is acknowledged only after the order transaction is commited. Malformed, invalid
+/// and conflicting orders are sent to the dead-letter sink, the ones failed to be stored
+/// because of the db are left to the source to redeliver.
+/// On shutdown the message being processed is finished before returning.
+pub async fn run_consumer(
+    mut source: Box<dyn OrderSource>,
+    dead_letters: Box<dyn DeadLetterSink>,
+    state: Arc<AppState>,
+    shutdown: Shutdown,
+) {
+    tracing::info!("starting order consumer for {}", source.name());
+
+    loop {
+        let next = tokio::select! {
+            biased;
+            _ = shutdown.wait() => {
+                tracing::info!("order consumer for {} stopped by shutdown", source.name());
+                return;
+            }
+            next = source.next_message() => next,
+        };
+        let Some(next) = next else {
+            break;
+        };
+        match next {
+            Ok(message) => process_message(message, dead_letters.as_ref(), &state).await,
+            Err(e) => tracing::warn!("failed to receive a message from {}: {}", source.name(), e),
+        }
+    }
+
+    tracing::info!(
+        "order source {} is exhausted, consumer stopped",
+        source.name()
+    );
+}
+
+async fn process_message(
+    message: SourceMessage,
+    dead_letters: &dyn DeadLetterSink,
+    state: &Arc<AppState>,
+) {
+    let mut order: Order = match serde_json::from_slice(&message.payload) {
+        Ok(order) => order,
+        Err(e) => {
+            let reason = format!("malformed order: {}", e);
+            return dead_letter(message, &reason, dead_letters).await;
+        }
+    };
+    if let Err(errors) = validate_order(&order) {
+        let reason = format!(
+            "invalid order: {}",
+            errors
+                .iter()
+                .format_with("; ", |e, f| f(&format_args!("{}: {}", e.field, e.message)))
+        );
+        return dead_letter(message, &reason, dead_letters).await;
+    }
+    order.status = OrderStatus::Created;
+
+    let mut attempt = 1;
+    loop {
+        let snapshot = state.cache.snapshot(&order.order_uid);
+        // redelivered messages must not fail on the already stored orders
+        let result = match idempotency::find_existing(&order, state).await {
+            Ok(Existing::None) => state.repo.insert(&order, None).await,
+            Ok(Existing::Identical(_)) => {
+                tracing::debug!("order {} is already stored", order.order_uid);
+                if let Err(e) = message.ack().await {
+                    tracing::warn!("failed to acknowledge a message: {}", e);
+                }
+                return;
+            }
+            Ok(Existing::Conflicting(differences)) => {
+                let reason = format!(
+                    "order {} conflicts with the stored one in: {}",
+                    order.order_uid,
+                    differences.iter().map(|d| &d.field).join(", ")
+                );
+                return dead_letter(message, &reason, dead_letters).await;
+            }
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => {
+                tracing::debug!("consumed order {}", order.order_uid);
+                state.cache.insert_unless_removed(order, snapshot);
+                if let Err(e) = message.ack().await {
+                    tracing::warn!("failed to acknowledge a message: {}", e);
+                }
+                return;
+            }
+            Err(e) if attempt < MAX_INSERT_ATTEMPTS => {
+                tracing::warn!(
+                    "attempt {} to store order {} failed: {}",
+                    attempt,
+                    order.order_uid,
+                    e
+                );
+                tokio::time::sleep(INSERT_RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                attempt += 1;
+            }
+            Err(e) if e.is_transient() => {
+                tracing::warn!("failed to store order {}: {}", order.order_uid, e);
+                return redeliver(message, &order.order_uid).await;
+            }
+            Err(e) => {
+                let reason = format!("failed to store order {}: {}", order.order_uid, e);
+                return dead_letter(message, &reason, dead_letters).await;
+            }
+        }
+    }
+}
+
+async fn dead_letter(message: SourceMessage, reason: &str, dead_letters: &dyn DeadLetterSink) {
+    tracing::warn!("sending a message to the dead-letter sink: {}", reason);
+    // not acknowledged messages are redelivered, so nothing is lost if the sink is unavailable
+    if let Err(e) = dead_letters.send(&message.payload, reason).await {
+        tracing::error!("failed to send a message to the dead-letter sink: {}", e);
+        return;
+    }
+    if let Err(e) = message.ack().await {
+        tracing::warn!("failed to acknowledge a dead-lettered message: {}", e);
+    }
+}
+
+async fn redeliver(message: SourceMessage, order_uid: &str) {
+    match message.nack(REDELIVERY_DELAY).await {
+        Ok(true) => tracing::info!(
+            "order {} is left to be redelivered in {:?}",
+            order_uid,
+            REDELIVERY_DELAY
+        ),
+        Ok(false) => tracing::error!(
+            "order {} is not stored, the source can't deliver it again",
+            order_uid
+        ),
+        // not acknowledged messages are redelivered after the ack wait anyway
+        Err(e) => tracing::warn!("failed to return a message to the source: {}", e),
+    }
+}
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use async_nats::jetstream::{
+    self,
+    consumer::{pull, AckPolicy},
+    AckKind,
+};
+use async_trait::async_trait;
+use futures::StreamExt;
+
+use super::{Acker, IngestError, OrderSource, SourceMessage};
+
+#[derive(Debug, Clone)]
+pub struct NatsConfig {
+    pub url: String,
+    // JetStream stream storing the orders, created if missing
+    pub stream: String,
+    pub subject: String,
+    // durable consumer name, keeps the position in the stream between restarts
+    pub consumer: String,
+}
+
+/// Durable pull consumer of a NATS JetStream stream.
+pub struct NatsSource {
+    name: String,
+    messages: pull::Stream,
+}
+
+impl NatsSource {
+    pub async fn connect(config: &NatsConfig) -> Result<NatsSource, IngestError> {
+        let client = async_nats::connect(&config.url).await?;
+        let jetstream = jetstream::new(client);
+
+        let stream = jetstream
+            .get_or_create_stream(jetstream::stream::Config {
+                name: config.stream.clone(),
+                subjects: vec![config.subject.clone()],
+                ..Default::default()
+            })
+            .await?;
+        let consumer = stream
+            .get_or_create_consumer(
+                &config.consumer,
+                pull::Config {
+                    durable_name: Some(config.consumer.clone()),
+                    ack_policy: AckPolicy::Explicit,
+                    filter_subject: config.subject.clone(),
+                    ..Default::default()
+                },
+            )
+            .await?;
+        let messages = consumer.messages().await?;
+
+        Ok(NatsSource {
+            name: format!("nats stream {}", config.stream),
+            messages,
+        })
+    }
+}
+
+#[async_trait]
+impl OrderSource for NatsSource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn next_message(&mut self) -> Option<Result<SourceMessage, IngestError>> {
+        let message = match self.messages.next().await? {
+            Ok(message) => message,
+            Err(e) => return Some(Err(e.into())),
+        };
+        let payload = message.payload.to_vec();
+        Some(Ok(SourceMessage::new(
+            payload,
+            Some(Box::new(NatsAcker(message))),
+        )))
+    }
+}
+
+struct NatsAcker(jetstream::Message);
+
+#[async_trait]
+impl Acker for NatsAcker {
+    async fn ack(self: Box<Self>) -> Result<(), IngestError> {
+        self.0.ack().await
+    }
+
+    async fn nack(self: Box<Self>, delay: Duration) -> Result<(), IngestError> {
+        self.0.ack_with(AckKind::Nak(Some(delay))).await
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use auth::{Auth, Required, Scope};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, patch, post},
+    Router,
+};
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use cache::OrderCache;
+use pii::Role;
+use repository::OrderRepository;
+use tokio_postgres_rustls::MakeRustlsConnect;
+use warmup::WarmupState;
+
+pub mod auth;
+pub mod batch;
+pub mod cache;
+pub mod config;
+pub mod db;
+pub mod error;
+pub mod handlers;
+pub mod health;
+pub mod idempotency;
+pub mod ingest;
+pub mod listing;
+pub mod metrics;
+pub mod migrations;
+pub mod money;
+pub mod openapi;
+pub mod pii;
+pub mod repository;
+pub mod request_id;
+pub mod schemas;
+pub mod shutdown;
+pub mod stats;
+pub mod status;
+pub mod tls;
+pub mod ui;
+pub mod validation;
+pub mod warmup;
+
+// batches are much larger than the default 2MB body limit
+const BATCH_BODY_LIMIT: usize = 64 * 1024 * 1024;
+
+// TLS is used depending on the `sslmode` of the connection config
+pub type DbPool = Pool<PostgresConnectionManager<MakeRustlsConnect>>;
+
+pub struct AppState {
+    pub repo: Box<dyn OrderRepository>,
+    pub cache: OrderCache,
+    pub warmup: WarmupState,
+    // role of the requests without the `X-Role` header
+    pub default_role: Role,
+    // `None` if the API keys are not required
+    pub auth: Option<Auth>,
+}
+
+/// Builds the service router with all the routes and middleware.
+///
+/// Every route except the probes, metrics and API docs requires an API key with the scope of its group.
+pub fn router(state: Arc<AppState>) -> Router {
+    let read = Router::new()
+        .route("/order/:order_uid", get(handlers::get_order))
+        .route("/order/:order_uid/history", get(handlers::order_history))
+        .route("/orders", get(handlers::list_orders))
+        .route("/", get(ui::index))
+        .route("/ui/order", get(ui::order_page))
+        .route("/stats/revenue", get(handlers::revenue_stats))
+        .route("/stats/brands", get(handlers::brand_stats))
+        .route("/stats/products", get(handlers::product_stats))
+        .route("/stats/delivery-costs", get(handlers::delivery_cost_stats))
+        .route(
+            "/stats/delivery-services",
+            get(handlers::delivery_service_stats),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Required::new(&state, Scope::Read),
+            auth::authenticate,
+        ));
+
+    let write = Router::new()
+        .route("/order/:order_uid", delete(handlers::delete_order))
+        .route(
+            "/order/:order_uid/status",
+            patch(handlers::update_order_status),
+        )
+        .route("/order", post(handlers::create_order))
+        .route(
+            "/orders/batch",
+            post(handlers::create_orders_batch).layer(DefaultBodyLimit::max(BATCH_BODY_LIMIT)),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Required::new(&state, Scope::Write),
+            auth::authenticate,
+        ));
+
+    let admin = Router::new()
+        .route(
+            "/customers/:customer_id/erasure",
+            post(handlers::erase_customer),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            Required::new(&state, Scope::Admin),
+            auth::authenticate,
+        ));
+
+    Router::new()
+        .merge(read)
+        .merge(write)
+        .merge(admin)
+        .route("/openapi.json", get(openapi::openapi_json))
+        .route("/docs", get(openapi::docs))
+        .route("/metrics", get(metrics::metrics))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .layer(middleware::from_fn(metrics::track_http))
+        .layer(middleware::from_fn(request_id::request_id))
+        .with_state(state)
+}
@@ -0,0 +1,256 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{types::ToSql, Client, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{error::AppError, money::Money, schemas::Order, status::OrderStatus};
+
+pub const DEFAULT_PAGE_SIZE: i64 = 50;
+pub const MAX_PAGE_SIZE: i64 = 500;
+
+/// Query parameters of the order search, all the filters are optional and combined with AND.
+#[derive(Deserialize, Debug, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OrderFilter {
+    pub customer_id: Option<String>,
+    pub track_number: Option<String>,
+    pub delivery_service: Option<String>,
+    // inclusive bounds of `date_created`
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub currency: Option<String>,
+    pub provider: Option<String>,
+    pub status: Option<OrderStatus>,
+    // orders having at least one item of the brand
+    pub brand: Option<String>,
+    pub limit: Option<i64>,
+    // opaque position returned as `next_cursor` by the previous page
+    pub cursor: Option<String>,
+}
+
+impl OrderFilter {
+    /// Requested page size, checked against the allowed range.
+    pub fn page_limit(&self) -> Result<i64, AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(AppError::BadRequest(format!(
+                "limit must be between 1 and {MAX_PAGE_SIZE}"
+            )));
+        }
+        Ok(limit)
+    }
+}
+
+/// Short view of an order returned by the search.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct OrderSummary {
+    pub order_uid: String,
+    pub track_number: String,
+    pub customer_id: String,
+    pub delivery_service: String,
+    pub date_created: DateTime<Utc>,
+    pub currency: String,
+    pub provider: String,
+    pub amount: Money,
+    pub items_count: i64,
+    pub status: OrderStatus,
+}
+
+impl OrderSummary {
+    fn from_row(row: &Row) -> OrderSummary {
+        OrderSummary {
+            order_uid: row.get("order_uid"),
+            track_number: row.get("track_number"),
+            customer_id: row.get("customer_id"),
+            delivery_service: row.get("delivery_service"),
+            date_created: row.get("date_created"),
+            currency: row.get("currency"),
+            provider: row.get("provider"),
+            amount: row.get("amount"),
+            items_count: row.get("items_count"),
+            status: row.get("status"),
+        }
+    }
+
+    pub fn from_order(order: &Order) -> OrderSummary {
+        OrderSummary {
+            order_uid: order.order_uid.clone(),
+            track_number: order.track_number.clone(),
+            customer_id: order.customer_id.clone(),
+            delivery_service: order.delivery_service.clone(),
+            date_created: order.date_created,
+            currency: order.payment.currency.clone(),
+            provider: order.payment.provider.clone(),
+            amount: order.payment.amount,
+            items_count: order.items.len() as i64,
+            status: order.status,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct OrderPage {
+    pub orders: Vec<OrderSummary>,
+    // `None` on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl OrderPage {
+    /// Builds the page from up to `limit + 1` summaries, the extra one tells whether there is a next page.
+    pub fn new(mut orders: Vec<OrderSummary>, limit: i64) -> OrderPage {
+        let next_cursor = if orders.len() as i64 > limit {
+            orders.truncate(limit as usize);
+            orders.last().map(|last| {
+                Cursor {
+                    date_created: last.date_created,
+                    order_uid: last.order_uid.clone(),
+                }
+                .encode()
+            })
+        } else {
+            None
+        };
+
+        OrderPage {
+            orders,
+            next_cursor,
+        }
+    }
+}
+
+/// Position in the `date_created DESC, order_uid DESC` ordering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub date_created: DateTime<Utc>,
+    pub order_uid: String,
+}
+
+impl Cursor {
+    /// Decodes the `cursor` query parameter.
+    pub fn parse(cursor: &str) -> Result<Cursor, AppError> {
+        Cursor::decode(cursor)
+            .ok_or_else(|| AppError::BadRequest("cursor is malformed".to_string()))
+    }
+
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.date_created
+                .to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.order_uid
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(cursor: &str) -> Option<Cursor> {
+        let raw = String::from_utf8(URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (date_created, order_uid) = raw.split_once('|')?;
+        Some(Cursor {
+            date_created: DateTime::parse_from_rfc3339(date_created)
+                .ok()?
+                .with_timezone(&Utc),
+            order_uid: order_uid.to_string(),
+        })
+    }
+}
+
+// Collects conditions along with their positional parameters.
+#[derive(Default)]
+struct Conditions {
+    clauses: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl Conditions {
+    // returns the placeholder of the added parameter
+    fn param<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    fn and(&mut self, clause: String) {
+        self.clauses.push(clause);
+    }
+}
+
+/// Returns a page of order summaries matching the filter, newest orders first.
+pub async fn list_orders(conn: &Client, filter: OrderFilter) -> Result<OrderPage, AppError> {
+    let limit = filter.page_limit()?;
+
+    let mut conditions = Conditions::default();
+    // the column is nullable since V1, such orders have no place in the ordering and can't be summarized
+    conditions.and("o.date_created IS NOT NULL".to_string());
+    if let Some(customer_id) = filter.customer_id {
+        let p = conditions.param(customer_id);
+        conditions.and(format!("o.customer_id = {p}"));
+    }
+    if let Some(track_number) = filter.track_number {
+        let p = conditions.param(track_number);
+        conditions.and(format!("o.track_number = {p}"));
+    }
+    if let Some(delivery_service) = filter.delivery_service {
+        let p = conditions.param(delivery_service);
+        conditions.and(format!("o.delivery_service = {p}"));
+    }
+    if let Some(created_from) = filter.created_from {
+        let p = conditions.param(created_from);
+        conditions.and(format!("o.date_created >= {p}"));
+    }
+    if let Some(created_to) = filter.created_to {
+        let p = conditions.param(created_to);
+        conditions.and(format!("o.date_created <= {p}"));
+    }
+    if let Some(currency) = filter.currency {
+        let p = conditions.param(currency);
+        conditions.and(format!("p.currency = {p}"));
+    }
+    if let Some(provider) = filter.provider {
+        let p = conditions.param(provider);
+        conditions.and(format!("p.provider = {p}"));
+    }
+    if let Some(status) = filter.status {
+        let p = conditions.param(status.as_str().to_string());
+        conditions.and(format!("o.status = {p}"));
+    }
+    if let Some(brand) = filter.brand {
+        let p = conditions.param(brand);
+        conditions.and(format!(
+            "EXISTS (SELECT 1 FROM items i WHERE i.order_uid = o.order_uid AND i.brand = {p})"
+        ));
+    }
+    if let Some(cursor) = filter.cursor {
+        let cursor = Cursor::parse(&cursor)?;
+        let date_created = conditions.param(cursor.date_created);
+        let order_uid = conditions.param(cursor.order_uid);
+        conditions.and(format!(
+            "(o.date_created, o.order_uid) < ({date_created}, {order_uid})"
+        ));
+    }
+
+    let where_clause = conditions.clauses.join(" AND ");
+    // one extra row tells whether there is a next page
+    let limit_param = conditions.param(limit + 1);
+
+    let query = format!(
+        "SELECT
+            o.order_uid, o.track_number, o.customer_id, o.delivery_service, o.date_created, o.status,
+            p.currency, p.provider, p.amount,
+            (SELECT count(*) FROM items i WHERE i.order_uid = o.order_uid) AS items_count
+        FROM orders o
+        JOIN payments p ON p.order_uid = o.order_uid
+        WHERE {where_clause}
+        ORDER BY o.date_created DESC, o.order_uid DESC
+        LIMIT {limit_param}"
+    );
+
+    let params: Vec<&(dyn ToSql + Sync)> = conditions
+        .params
+        .iter()
+        .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+        .collect();
+    let rows = conn.query(&query, &params).await?;
+
+    let orders = rows.iter().map(OrderSummary::from_row).collect();
+    Ok(OrderPage::new(orders, limit))
+}
@@ -1,56 +1,24 @@
-use std::{
-    collections::HashMap,
-    env,
-    sync::{Arc, RwLock},
-};
-
-use itertools::Itertools;
+use std::{future::IntoFuture, net::SocketAddr, sync::Arc, time::Instant};
 
-use axum::{
-    extract::{Json, Path, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
-    routing::{get, post},
-    Router,
-};
 use bb8::Pool;
 use bb8_postgres::PostgresConnectionManager;
 use clap::Parser;
-use schemas::{Delivery, Item, Order, Payment};
-use tokio_postgres::{types::ToSql, Config, NoTls};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod schemas;
-
-type DbPoolNoTsl = Pool<PostgresConnectionManager<NoTls>>;
-// currrently using simple HashMap, it might be a good idea to use LRU
-type RwCacheOrder = Arc<RwLock<HashMap<String, schemas::Order>>>;
-
-#[derive(Parser, Debug)]
-struct Args {
-    // Port to start a server at
-    #[clap(short = 'p', long, default_value = "3000")]
-    server_port: String,
-
-    // Port to listen to a PostgreSQL DB
-    #[clap(short = 'd', long, default_value = "5432")]
-    pg_port: u16,
-
-    // Host to listen to a PostgreSQL DB
-    #[clap(short = 'l', long, default_value = "localhost")]
-    pg_host: String,
-}
-
-const DEFAULT_POSTGRES_USER: &str = "postgres";
-// it looks like a perfect vulnerability to hack into DB
-const DEFAULT_POSTGRES_PASSWORD: &str = "postgres";
-const DEFAULT_POSTGRES_DB: &str = "postgres";
-
-#[derive(Clone)]
-struct AppState {
-    pool: DbPoolNoTsl,
-    cache: RwCacheOrder,
-}
+use view_service::{
+    auth::{self, Auth},
+    cache::OrderCache,
+    config::{Args, Config, IngestConfig},
+    error::AppError,
+    ingest::{
+        self, DeadLetterSink, FileDeadLetterSink, FileSource, IngestError, NatsDeadLetterSink,
+        NatsSource, OrderSource, SourceKind,
+    },
+    metrics, migrations,
+    repository::PostgresRepository,
+    router, shutdown, tls,
+    warmup::{self, WarmupState, WarmupStatus},
+    AppState, DbPool,
+};
 
 #[tokio::main]
 async fn main() {
@@ -62,274 +30,206 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let args = Args::parse();
+    let config = match Config::load(Args::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     // init DB connection
-    let mut db_config = Config::new();
-    db_config.user(match env::var("POSTGRES_USER") {
-        Ok(usr) => usr,
-        Err(_) => DEFAULT_POSTGRES_USER.to_string(),
-    });
-    db_config.password(match env::var("POSTGRES_PASSWORD") {
-        Ok(pswd) => pswd,
-        Err(_) => DEFAULT_POSTGRES_PASSWORD.to_string(),
-    });
-    db_config.dbname(match env::var("POSTGRES_DB") {
-        Ok(dbname) => dbname,
-        Err(_) => DEFAULT_POSTGRES_DB.to_string(),
-    });
-    db_config.port(args.pg_port);
-    db_config.host(args.pg_host);
-
-    tracing::debug!("built a db config with values {:?}", db_config);
+    tracing::debug!("built a db config with values {:?}", config.postgres);
+
+    let tls = tls::make_connector(&config.tls).expect("failed to configure db TLS");
+    let manager = PostgresConnectionManager::new(config.postgres.clone(), tls);
+    let pool: DbPool = Pool::builder()
+        .max_size(config.pool.max_size)
+        .min_idle(config.pool.min_idle)
+        .connection_timeout(config.pool.connection_timeout)
+        .idle_timeout(Some(config.pool.idle_timeout))
+        .max_lifetime(Some(config.pool.max_lifetime))
+        .build(manager)
+        .await
+        .unwrap();
 
-    let manager = PostgresConnectionManager::new(db_config, NoTls);
-    let pool: Pool<PostgresConnectionManager<NoTls>> =
-        Pool::builder().build(manager).await.unwrap();
+    // bring the db schema up to date before serving anything
+    let applied = {
+        let mut conn = pool.get().await.expect("failed to connect to the db");
+        migrations::run(&mut conn)
+            .await
+            .expect("failed to apply db migrations")
+    };
+    tracing::info!("{} db migrations applied", applied.len());
+    if config.migrate_only {
+        return;
+    }
+    if let Some(new_key) = &config.create_api_key {
+        let repo = PostgresRepository::new(pool.clone());
+        match auth::create_key(&repo, new_key).await {
+            Ok((key, stored)) => {
+                tracing::info!("API key {} created with id {}", stored.name, stored.id);
+                // the key is shown only once, only its hash is stored
+                println!("{key}");
+            }
+            Err(e) => {
+                tracing::error!("failed to create an API key: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if config.auth_disabled {
+        tracing::warn!("API keys are not required, the API is open to anyone");
+    }
 
     // init cache layer
-    let cache = Arc::new(RwLock::new(HashMap::new()));
+    let cache = OrderCache::new(config.cache.clone());
     // create new state
-    let app_state = Arc::new(AppState { pool, cache });
+    let app_state = Arc::new(AppState {
+        repo: Box::new(PostgresRepository::new(pool.clone())),
+        cache,
+        warmup: WarmupState::default(),
+        default_role: config.default_role,
+        auth: (!config.auth_disabled).then(Auth::new),
+    });
+
+    // stop everything on SIGINT or SIGTERM
+    let (trigger, shutdown) = shutdown::channel();
+    tokio::spawn(async move {
+        let signal = shutdown::signal().await;
+        tracing::info!("received {}, shutting down", signal);
+        trigger.trigger();
+    });
+
+    // the cache is warmed up before the listener is bound, the status is kept for the readiness probe
+    if config.skip_warmup {
+        tracing::info!("cache warm-up is skipped");
+        app_state.warmup.set(WarmupStatus::Skipped);
+    } else {
+        // no reason to load more orders than the cache is able to hold
+        let limit = config.warmup_orders.min(config.cache.max_entries.get());
+        app_state.warmup.set(WarmupStatus::InProgress);
+        let result = tokio::select! {
+            result = warmup::warm_up_cache(&pool, &app_state.cache, limit) => result,
+            _ = shutdown.wait() => {
+                tracing::info!("shutdown requested during the cache warm-up");
+                return;
+            }
+        };
+        match result {
+            Ok(cached) => {
+                tracing::info!("cache warm-up finished, {} orders cached", cached);
+                app_state.warmup.set(WarmupStatus::Done { cached });
+            }
+            // the service is still functional with a cold cache
+            Err(e) => {
+                tracing::warn!("cache warm-up failed: {}", e);
+                // the status is shown by the open readiness probe
+                app_state.warmup.set(WarmupStatus::Failed {
+                    error: AppError::from(e).public_message(),
+                });
+            }
+        }
+    }
+
+    // start consuming orders from the stream if configured
+    let mut consumer_task = None;
+    if config.ingest.source != SourceKind::None {
+        let (source, dead_letters) = connect_ingestion(&config.ingest)
+            .await
+            .expect("failed to start order ingestion");
+        consumer_task = Some(tokio::spawn(ingest::run_consumer(
+            source,
+            dead_letters,
+            app_state.clone(),
+            shutdown.clone(),
+        )));
+    }
 
     // start server
-    let app = Router::new()
-        .route("/order/:order_uid", get(get_order))
-        .route("/order", post(create_order))
-        .with_state(app_state);
+    let app = router(app_state.clone());
 
     let listener =
-        tokio::net::TcpListener::bind(format!("127.0.0.1:{port}", port = args.server_port))
+        tokio::net::TcpListener::bind(SocketAddr::new(config.bind_address, config.server_port))
             .await
             .unwrap();
 
     tracing::debug!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
-}
-
-// process order post
-async fn create_order(
-    State(state): State<Arc<AppState>>,
-    axum::Json(order): axum::Json<schemas::Order>,
-) -> Response {
-    tracing::debug!("order post request");
-    match inser_order_tx(&order, state.clone()).await {
-        Ok(_) => {
-            let order_uid = order.order_uid.clone();
-            let _ = state.cache.write().unwrap().insert(order_uid, order);
-            tracing::debug!("transaction commited");
-            (
-                StatusCode::CREATED,
-                "Order successfully created".to_string(),
-            )
-                .into_response()
+    let server = {
+        let shutdown = shutdown.clone();
+        // new connections are refused once the shutdown is triggered
+        tokio::spawn(
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async move { shutdown.wait().await })
+                .into_future(),
+        )
+    };
+
+    shutdown.wait().await;
+    let started = Instant::now();
+    let drain = async {
+        match server.await {
+            Ok(Err(e)) => tracing::error!("server failed: {}", e),
+            Err(e) => tracing::error!("server task failed: {}", e),
+            Ok(Ok(())) => {}
         }
-        Err(e) => {
-            tracing::debug!("transaction reverted");
-            e.into_response()
+        if let Some(task) = consumer_task {
+            if let Err(e) = task.await {
+                tracing::error!("order consumer task failed: {}", e);
+            }
         }
-    }
-}
-
-async fn inser_order_tx(order: &Order, state: Arc<AppState>) -> Result<(), (StatusCode, String)> {
-    let mut conn = state.pool.get().await.map_err(internal_error).unwrap();
-
-    let transaction = conn.transaction().await.map_err(internal_error)?;
-    tracing::debug!("transaction started");
-
-    let order_query =
-    "INSERT INTO orders (order_uid, track_number, entry, locale, internal_signature, customer_id, delivery_service, shardkey, sm_id, date_created, oof_shard) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
-    let _order_result = transaction
-        .execute(
-            order_query,
-            &[
-                &order.order_uid,
-                &order.track_number,
-                &order.entry,
-                &order.locale,
-                &order.internal_signature,
-                &order.customer_id,
-                &order.delivery_service,
-                &order.shardkey,
-                &order.sm_id,
-                &order.date_created,
-                &order.oof_shard,
-            ],
-        )
+    };
+    let drained = tokio::time::timeout(config.shutdown_timeout, drain)
         .await
-        .map_err(internal_error)?;
-
-    let delivery_query =
-        "INSERT INTO deliveries (order_uid, name, phone, zip, city, address, region, email) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)";
-    let delivery = &order.delivery;
-    let _delivery_result = transaction
-        .execute(
-            delivery_query,
-            &[
-                &order.order_uid,
-                &delivery.name,
-                &delivery.phone,
-                &delivery.zip,
-                &delivery.city,
-                &delivery.address,
-                &delivery.region,
-                &delivery.email,
-            ],
-        )
-        .await
-        .map_err(internal_error)?;
-
-    tracing::debug!("performed delivery insertion");
+        .is_ok();
+
+    // requests still in flight are dropped, so their transactions are rolled back by the db
+    let summary = metrics::summary();
+    if !drained {
+        tracing::warn!(
+            "shutdown deadline of {:?} exceeded, aborting {} in-flight requests",
+            config.shutdown_timeout,
+            summary.requests_in_flight
+        );
+    }
 
-    let items_params: Vec<_> = order
-        .items
-        .iter()
-        .flat_map(|item| {
-            [
-                &order.order_uid as &(dyn ToSql + Sync),
-                &item.chrt_id,
-                &item.track_number,
-                &item.price,
-                &item.rid,
-                &item.name,
-                &item.sale,
-                &item.size,
-                &item.total_price,
-                &item.nm_id,
-                &item.brand,
-                &item.status,
-            ]
-        })
-        .collect();
-    // preparing placeholders for the query params
-    let items_query = format!(
-        "INSERT INTO items (order_uid, chrt_id, track_number, price, rid, name, sale, size, total_price, nm_id, brand, status) VALUES {}",
-        (1..items_params.len()+1)
-            .tuples()
-            .format_with(", ", |(a, b, c, d, e, ff, g, h, i, j, k, l), f| {
-                f(&format_args!("(${a}, ${b}, ${c},  ${d}, ${e}, ${ff}, ${g}, ${h}, ${i}, ${j}, ${k}, ${l})"))
-            }),
+    let pool_state = pool.state();
+    tracing::info!(
+        "closing db pool with {} connections, {} idle",
+        pool_state.connections,
+        pool_state.idle_connections
+    );
+    drop(app_state);
+    drop(pool);
+    // lets the dropped connections send the termination message before the runtime stops
+    tokio::task::yield_now().await;
+
+    tracing::info!(
+        "shutdown finished in {:?}: {} requests served, {} aborted, {} transactions committed, {} rolled back",
+        started.elapsed(),
+        summary.requests_served,
+        if drained { 0 } else { summary.requests_in_flight },
+        summary.transactions_committed,
+        summary.transactions_rolled_back,
     );
-    let _items_res = transaction
-        .execute(items_query.as_str(), &items_params)
-        .await
-        .map_err(internal_error)?;
-
-    tracing::debug!("performed items insertion");
-
-    let payment = &order.payment;
-    let payment_query =
-        "INSERT INTO payments (order_uid, transaction_id, request_id, currency, provider, amount, payment_dt, bank, delivery_cost, goods_total, custom_fee) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)";
-    let _payment_result = transaction
-        .execute(
-            payment_query,
-            &[
-                &order.order_uid,
-                &payment.transaction,
-                &payment.request_id,
-                &payment.currency,
-                &payment.provider,
-                &payment.amount,
-                &payment.payment_dt,
-                &payment.bank,
-                &payment.delivery_cost,
-                &payment.goods_total,
-                &payment.custom_fee,
-            ],
-        )
-        .await
-        .map_err(internal_error)?;
-
-    tracing::debug!("performed payment insertion");
-
-    transaction.commit().await.map_err(internal_error)?;
-    Ok(())
 }
 
-// process order get
-async fn get_order(Path(order_uid): Path<String>, State(state): State<Arc<AppState>>) -> Response {
-    // check cahce
-    {
-        tracing::debug!("checking cache for order with uid: {}", order_uid);
-        let cache = state.cache.read().map_err(internal_error).unwrap();
-        if let Some(order) = cache.get(&order_uid) {
-            return (StatusCode::OK, Json(order)).into_response();
-        }
-    }
-
-    tracing::debug!("no cahce hit");
-
-    // no cache hit
-    match collect_order(order_uid, state.clone()).await {
-        Ok(order) => {
-            let order_uid = order.order_uid.clone();
-            let _ = state
-                .cache
-                .write()
-                .unwrap()
-                .insert(order_uid, order.clone());
-            (StatusCode::OK, Json(order)).into_response()
+async fn connect_ingestion(
+    config: &IngestConfig,
+) -> Result<(Box<dyn OrderSource>, Box<dyn DeadLetterSink>), IngestError> {
+    let source: Box<dyn OrderSource> = match config.source {
+        SourceKind::File => Box::new(FileSource::open(&config.file).await?),
+        SourceKind::Nats => Box::new(NatsSource::connect(&config.nats).await?),
+        SourceKind::None => unreachable!("ingestion is not started without a source"),
+    };
+
+    let dead_letters: Box<dyn DeadLetterSink> = match &config.nats_dead_letter_subject {
+        Some(subject) if config.source == SourceKind::Nats => {
+            Box::new(NatsDeadLetterSink::connect(&config.nats.url, subject.clone()).await?)
         }
-        Err(e) => e.into_response(),
-    }
-}
-
-async fn collect_order(
-    order_uid: String,
-    state: Arc<AppState>,
-) -> Result<Order, (StatusCode, String)> {
-    let conn = state.pool.get().await.map_err(internal_error).unwrap();
-    // request items
-    let items_rows = conn
-        .query("SELECT * FROM items WHERE order_uid = $1", &[&order_uid])
-        .await
-        .map_err(not_found_err)?;
-
-    let items = items_rows
-        .into_iter()
-        .map(|row| Item::from_row(&row))
-        .collect::<Vec<_>>();
-
-    // request delivery
-    let delivery_row = conn
-        .query_one(
-            "SELECT * FROM deliveries WHERE order_uid = $1",
-            &[&order_uid],
-        )
-        .await
-        .map_err(not_found_err)?;
-    let delivery = Delivery::from_row(&delivery_row);
-
-    // request payment
-    let payment_row = conn
-        .query_one("SELECT * FROM payments WHERE order_uid = $1", &[&order_uid])
-        .await
-        .map_err(not_found_err)?;
-    let payment = Payment::from_row(&payment_row);
-
-    let order_row = conn
-        .query_one("SELECT * FROM orders WHERE order_uid = $1", &[&order_uid])
-        .await
-        .map_err(not_found_err)?;
-
-    Ok(Order::from_row(&order_row, delivery, payment, items))
-}
-
-/// Utility function for mapping any error into a `500 Internal Server Error`
-/// response.
-fn internal_error<E>(err: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
-}
+        _ => Box::new(FileDeadLetterSink::open(&config.dead_letter_file).await?),
+    };
 
-fn not_found_err<E>(_: E) -> (StatusCode, String)
-where
-    E: std::error::Error,
-{
-    (
-        StatusCode::NOT_FOUND,
-        "The order has not been found in the sistem".to_string(),
-    )
+    Ok((source, dead_letters))
 }
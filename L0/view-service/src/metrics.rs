@@ -0,0 +1,259 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::Instant,
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+
+use crate::AppState;
+
+// route label of the requests not matching any route, keeps the label cardinality bounded
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Service metrics, cache and pool ones are refreshed on every scrape.
+struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    http_requests_in_flight: IntGauge,
+    cache_hits: IntCounter,
+    cache_misses: IntCounter,
+    cache_evictions: IntCounter,
+    cache_expirations: IntCounter,
+    cache_entries: IntGauge,
+    cache_bytes: IntGauge,
+    pool_connections: IntGauge,
+    pool_idle_connections: IntGauge,
+    pool_gets: IntCounterVec,
+    pool_wait_seconds: prometheus::Counter,
+    db_transactions: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new_custom(Some("view_service".to_string()), None)
+            .expect("metrics prefix is valid");
+        // registration fails only on a name clash, which is a programming error
+        fn register<T: Collector + Clone + 'static>(registry: &Registry, metric: T) -> T {
+            registry
+                .register(Box::new(metric.clone()))
+                .expect("metric names are unique");
+            metric
+        }
+
+        Metrics {
+            http_requests: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("http_requests_total", "Processed HTTP requests"),
+                    &["method", "route", "status"],
+                )
+                .unwrap(),
+            ),
+            http_request_duration: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new(
+                        "http_request_duration_seconds",
+                        "HTTP request processing time",
+                    ),
+                    &["method", "route"],
+                )
+                .unwrap(),
+            ),
+            http_requests_in_flight: register(
+                &registry,
+                IntGauge::new("http_requests_in_flight", "HTTP requests being processed").unwrap(),
+            ),
+            cache_hits: register(
+                &registry,
+                IntCounter::new("cache_hits_total", "Order cache hits").unwrap(),
+            ),
+            cache_misses: register(
+                &registry,
+                IntCounter::new("cache_misses_total", "Order cache misses").unwrap(),
+            ),
+            cache_evictions: register(
+                &registry,
+                IntCounter::new("cache_evictions_total", "Orders evicted from the cache").unwrap(),
+            ),
+            cache_expirations: register(
+                &registry,
+                IntCounter::new("cache_expirations_total", "Cached orders expired by TTL").unwrap(),
+            ),
+            cache_entries: register(
+                &registry,
+                IntGauge::new("cache_entries", "Orders in the cache").unwrap(),
+            ),
+            cache_bytes: register(
+                &registry,
+                IntGauge::new("cache_bytes", "Approximate size of the cached orders").unwrap(),
+            ),
+            pool_connections: register(
+                &registry,
+                IntGauge::new("db_pool_connections", "Connections managed by the pool").unwrap(),
+            ),
+            pool_idle_connections: register(
+                &registry,
+                IntGauge::new("db_pool_idle_connections", "Idle connections of the pool").unwrap(),
+            ),
+            pool_gets: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("db_pool_gets_total", "Connection checkouts from the pool"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            pool_wait_seconds: register(
+                &registry,
+                prometheus::Counter::new(
+                    "db_pool_wait_seconds_total",
+                    "Time spent waiting for a pool connection",
+                )
+                .unwrap(),
+            ),
+            db_transactions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("db_transactions_total", "Finished order transactions"),
+                    &["outcome"],
+                )
+                .unwrap(),
+            ),
+            registry,
+        }
+    }
+}
+
+/// Records the outcome of an order storing transaction.
+pub fn record_transaction(committed: bool) {
+    let outcome = if committed { "commit" } else { "rollback" };
+    METRICS.db_transactions.with_label_values(&[outcome]).inc();
+}
+
+/// Totals reported when the service shuts down.
+#[derive(Debug, Clone, Copy)]
+pub struct Summary {
+    pub requests_served: u64,
+    pub requests_in_flight: i64,
+    pub transactions_committed: u64,
+    pub transactions_rolled_back: u64,
+}
+
+pub fn summary() -> Summary {
+    let requests_served = METRICS
+        .http_requests
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .map(|metric| metric.get_counter().get_value() as u64)
+        .sum();
+    Summary {
+        requests_served,
+        requests_in_flight: METRICS.http_requests_in_flight.get(),
+        transactions_committed: METRICS.db_transactions.with_label_values(&["commit"]).get(),
+        transactions_rolled_back: METRICS
+            .db_transactions
+            .with_label_values(&["rollback"])
+            .get(),
+    }
+}
+
+/// Counts requests and measures their latency per matched route.
+pub async fn track_http(request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+
+    METRICS.http_requests_in_flight.inc();
+    let response = next.run(request).await;
+    METRICS.http_requests_in_flight.dec();
+
+    METRICS
+        .http_request_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    METRICS
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+    response
+}
+
+// counters mirror cumulative values kept elsewhere, so they are advanced to the current value
+fn advance(counter: &IntCounter, value: u64) {
+    let current = counter.get();
+    if value > current {
+        counter.inc_by(value - current);
+    }
+}
+
+fn refresh(state: &AppState) {
+    let cache = state.cache.stats();
+    advance(&METRICS.cache_hits, cache.hits);
+    advance(&METRICS.cache_misses, cache.misses);
+    advance(&METRICS.cache_evictions, cache.evictions);
+    advance(&METRICS.cache_expirations, cache.expirations);
+    METRICS.cache_entries.set(cache.entries as i64);
+    METRICS.cache_bytes.set(cache.bytes as i64);
+
+    let Some(pool) = state.repo.pool_state() else {
+        return;
+    };
+    METRICS.pool_connections.set(pool.connections.into());
+    METRICS
+        .pool_idle_connections
+        .set(pool.idle_connections.into());
+    let stats = pool.statistics;
+    for (outcome, value) in [
+        ("direct", stats.get_direct),
+        ("waited", stats.get_waited),
+        ("timed_out", stats.get_timed_out),
+    ] {
+        advance(&METRICS.pool_gets.with_label_values(&[outcome]), value);
+    }
+    let waited = stats.get_wait_time.as_secs_f64();
+    let wait_counted = METRICS.pool_wait_seconds.get();
+    if waited > wait_counted {
+        METRICS.pool_wait_seconds.inc_by(waited - wait_counted);
+    }
+}
+
+/// Returns the metrics in the Prometheus text format.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "probes",
+    responses((status = 200, body = String, content_type = "text/plain; version=0.0.4")),
+)]
+pub async fn metrics(State(state): State<Arc<AppState>>) -> Response {
+    refresh(&state);
+
+    let mut buffer = Vec::new();
+    let encoder = TextEncoder::new();
+    if let Err(e) = encoder.encode(&METRICS.registry.gather(), &mut buffer) {
+        tracing::error!("failed to encode metrics: {}", e);
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    }
+    (
+        [(header::CONTENT_TYPE, encoder.format_type().to_string())],
+        buffer,
+    )
+        .into_response()
+}
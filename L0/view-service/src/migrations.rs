@@ -0,0 +1,219 @@
+use std::fmt;
+
+use tokio_postgres::Client;
+
+// migrations embedded into the binary, a new file has to be listed here as well
+const MIGRATION_FILES: &[(&str, &str)] = &[
+    (
+        "V1_orders_init.sql",
+        include_str!("../migrations/V1_orders_init.sql"),
+    ),
+    (
+        "V2_payments_init.sql",
+        include_str!("../migrations/V2_payments_init.sql"),
+    ),
+    (
+        "V3_items_init.sql",
+        include_str!("../migrations/V3_items_init.sql"),
+    ),
+    (
+        "V4_deliveries_init.sql",
+        include_str!("../migrations/V4_deliveries_init.sql"),
+    ),
+    (
+        "V5_idempotency_keys_init.sql",
+        include_str!("../migrations/V5_idempotency_keys_init.sql"),
+    ),
+    (
+        "V6_items_order_uid_index.sql",
+        include_str!("../migrations/V6_items_order_uid_index.sql"),
+    ),
+    (
+        "V7_orders_search_indexes.sql",
+        include_str!("../migrations/V7_orders_search_indexes.sql"),
+    ),
+    (
+        "V8_order_status.sql",
+        include_str!("../migrations/V8_order_status.sql"),
+    ),
+    (
+        "V9_order_events_cascade.sql",
+        include_str!("../migrations/V9_order_events_cascade.sql"),
+    ),
+    (
+        "V10_stats_indexes.sql",
+        include_str!("../migrations/V10_stats_indexes.sql"),
+    ),
+    (
+        "V11_pii_erasures.sql",
+        include_str!("../migrations/V11_pii_erasures.sql"),
+    ),
+    (
+        "V12_api_keys.sql",
+        include_str!("../migrations/V12_api_keys.sql"),
+    ),
+];
+
+// arbitrary key of the advisory lock held while migrating,
+// so that several instances started at once don't apply the same migration
+const MIGRATION_LOCK_KEY: i64 = 0x7669_6577_5f6d_6967;
+
+const CREATE_HISTORY_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations
+(
+    version     BIGINT NOT NULL PRIMARY KEY,
+    name        VARCHAR NOT NULL,
+    applied_at  TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+)";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    // file name doesn't follow the `V<n>_<description>.sql` pattern
+    InvalidName(String),
+    DuplicateVersion(i64),
+    Database(tokio_postgres::Error),
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::InvalidName(name) => {
+                write!(
+                    f,
+                    "migration {} is not named as V<n>_<description>.sql",
+                    name
+                )
+            }
+            MigrationError::DuplicateVersion(version) => {
+                write!(f, "several migrations have version {}", version)
+            }
+            MigrationError::Database(e) => write!(f, "migration failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(err: tokio_postgres::Error) -> Self {
+        MigrationError::Database(err)
+    }
+}
+
+/// Returns the embedded migrations ordered by version.
+pub fn migrations() -> Result<Vec<Migration>, MigrationError> {
+    let mut migrations = MIGRATION_FILES
+        .iter()
+        .map(|(name, sql)| {
+            Ok(Migration {
+                version: parse_version(name)?,
+                name,
+                sql,
+            })
+        })
+        .collect::<Result<Vec<_>, MigrationError>>()?;
+
+    migrations.sort_by_key(|m| m.version);
+    if let Some(pair) = migrations.windows(2).find(|w| w[0].version == w[1].version) {
+        return Err(MigrationError::DuplicateVersion(pair[0].version));
+    }
+    Ok(migrations)
+}
+
+/// Extracts `n` from a `V<n>_<description>.sql` file name.
+pub fn parse_version(name: &str) -> Result<i64, MigrationError> {
+    let invalid = || MigrationError::InvalidName(name.to_string());
+    let (version, description) = name
+        .strip_prefix('V')
+        .and_then(|rest| rest.strip_suffix(".sql"))
+        .and_then(|rest| rest.split_once('_'))
+        .ok_or_else(invalid)?;
+    if description.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    version.parse().map_err(|_| invalid())
+}
+
+/// Version of the latest embedded migration, the schema the service expects.
+pub fn latest_version() -> Option<i64> {
+    MIGRATION_FILES
+        .iter()
+        .filter_map(|(name, _)| parse_version(name).ok())
+        .max()
+}
+
+/// Returns the latest applied migration version, `None` if nothing is applied yet.
+pub async fn current_version(conn: &Client) -> Result<Option<i64>, tokio_postgres::Error> {
+    let exists: bool = conn
+        .query_one("SELECT to_regclass('schema_migrations') IS NOT NULL", &[])
+        .await?
+        .get(0);
+    if !exists {
+        return Ok(None);
+    }
+    let row = conn
+        .query_one("SELECT max(version) FROM schema_migrations", &[])
+        .await?;
+    Ok(row.get(0))
+}
+
+/// Applies the pending migrations in order, each one in its own transaction.
+///
+/// Returns versions of the applied migrations.
+pub async fn run(conn: &mut Client) -> Result<Vec<i64>, MigrationError> {
+    let migrations = migrations()?;
+
+    conn.execute("SELECT pg_advisory_lock($1)", &[&MIGRATION_LOCK_KEY])
+        .await?;
+    let result = apply_pending(conn, &migrations).await;
+    // the lock is released with the session anyway, so failing to unlock isn't fatal
+    if let Err(e) = conn
+        .execute("SELECT pg_advisory_unlock($1)", &[&MIGRATION_LOCK_KEY])
+        .await
+    {
+        tracing::warn!("failed to release the migration lock: {}", e);
+    }
+    result
+}
+
+async fn apply_pending(
+    conn: &mut Client,
+    migrations: &[Migration],
+) -> Result<Vec<i64>, MigrationError> {
+    conn.batch_execute(CREATE_HISTORY_TABLE).await?;
+    let applied: Vec<i64> = conn
+        .query("SELECT version FROM schema_migrations", &[])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    // the db has been migrated by a newer build, e.g. during a rolling update
+    for unknown in applied
+        .iter()
+        .filter(|v| !migrations.iter().any(|m| m.version == **v))
+    {
+        tracing::warn!("db has migration {} unknown to this build", unknown);
+    }
+
+    let mut newly_applied = Vec::new();
+    for migration in migrations.iter().filter(|m| !applied.contains(&m.version)) {
+        tracing::info!("applying migration {}", migration.name);
+        let transaction = conn.transaction().await?;
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+        transaction.commit().await?;
+        newly_applied.push(migration.version);
+    }
+    Ok(newly_applied)
+}
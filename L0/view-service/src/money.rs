@@ -0,0 +1,237 @@
+use std::{error::Error, fmt};
+
+use bytes::BytesMut;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::{to_sql_checked, FromSql, IsNull, ToSql, Type};
+use utoipa::ToSchema;
+
+/// Amount of money in the minor units of the order currency, e.g. cents for `USD`.
+///
+/// It is serialized as a bare integer, so the JSON model is the same as with plain `i64` fields.
+/// The currency itself is stored once per order in `payment.currency`.
+#[derive(
+    Serialize,
+    Deserialize,
+    ToSchema,
+    Debug,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+)]
+#[serde(transparent)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    pub const fn from_minor(minor: i64) -> Money {
+        Money(minor)
+    }
+
+    pub const fn minor(self) -> i64 {
+        self.0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn checked_add(self, other: Money) -> Option<Money> {
+        self.0.checked_add(other.0).map(Money)
+    }
+
+    /// Sums the amounts, `None` on overflow.
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Money>) -> Option<Money> {
+        amounts
+            .into_iter()
+            .try_fold(Money::ZERO, |acc, amount| acc.checked_add(amount))
+    }
+
+    /// Applies a sale in percent, the result may be rounded either way, so both variants are returned.
+    pub fn with_sale(self, sale: i64) -> Option<(Money, Money)> {
+        if !(0..=100).contains(&sale) || self.is_negative() {
+            return None;
+        }
+        let discounted = self.0.checked_mul(100 - sale)?;
+        // discounted is not negative, so the division rounds down
+        let floor = discounted / 100;
+        let ceil = floor + i64::from(discounted % 100 != 0);
+        Some((Money(floor), Money(ceil)))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(
+        &self,
+        ty: &Type,
+        out: &mut BytesMut,
+    ) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.0.to_sql(ty, out)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <i64 as ToSql>::accepts(ty)
+    }
+
+    to_sql_checked!();
+}
+
+impl<'a> FromSql<'a> for Money {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        i64::from_sql(ty, raw).map(Money)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <i64 as FromSql>::accepts(ty)
+    }
+}
+
+/// ISO 4217 currency with the number of digits after the decimal separator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Currency {
+    code: &'static str,
+    minor_units: u32,
+}
+
+// active ISO 4217 codes sorted for the binary search, funds and precious metals are left out
+#[rustfmt::skip]
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AED", 2), ("AFN", 2), ("ALL", 2), ("AMD", 2), ("ANG", 2), ("AOA", 2), ("ARS", 2), ("AUD", 2),
+    ("AWG", 2), ("AZN", 2), ("BAM", 2), ("BBD", 2), ("BDT", 2), ("BGN", 2), ("BHD", 3), ("BIF", 0),
+    ("BMD", 2), ("BND", 2), ("BOB", 2), ("BRL", 2), ("BSD", 2), ("BTN", 2), ("BWP", 2), ("BYN", 2),
+    ("BZD", 2), ("CAD", 2), ("CDF", 2), ("CHF", 2), ("CLP", 0), ("CNY", 2), ("COP", 2), ("CRC", 2),
+    ("CUP", 2), ("CVE", 2), ("CZK", 2), ("DJF", 0), ("DKK", 2), ("DOP", 2), ("DZD", 2), ("EGP", 2),
+    ("ERN", 2), ("ETB", 2), ("EUR", 2), ("FJD", 2), ("FKP", 2), ("GBP", 2), ("GEL", 2), ("GHS", 2),
+    ("GIP", 2), ("GMD", 2), ("GNF", 0), ("GTQ", 2), ("GYD", 2), ("HKD", 2), ("HNL", 2), ("HTG", 2),
+    ("HUF", 2), ("IDR", 2), ("ILS", 2), ("INR", 2), ("IQD", 3), ("IRR", 2), ("ISK", 0), ("JMD", 2),
+    ("JOD", 3), ("JPY", 0), ("KES", 2), ("KGS", 2), ("KHR", 2), ("KMF", 0), ("KPW", 2), ("KRW", 0),
+    ("KWD", 3), ("KYD", 2), ("KZT", 2), ("LAK", 2), ("LBP", 2), ("LKR", 2), ("LRD", 2), ("LSL", 2),
+    ("LYD", 3), ("MAD", 2), ("MDL", 2), ("MGA", 2), ("MKD", 2), ("MMK", 2), ("MNT", 2), ("MOP", 2),
+    ("MRU", 2), ("MUR", 2), ("MVR", 2), ("MWK", 2), ("MXN", 2), ("MYR", 2), ("MZN", 2), ("NAD", 2),
+    ("NGN", 2), ("NIO", 2), ("NOK", 2), ("NPR", 2), ("NZD", 2), ("OMR", 3), ("PAB", 2), ("PEN", 2),
+    ("PGK", 2), ("PHP", 2), ("PKR", 2), ("PLN", 2), ("PYG", 0), ("QAR", 2), ("RON", 2), ("RSD", 2),
+    ("RUB", 2), ("RWF", 0), ("SAR", 2), ("SBD", 2), ("SCR", 2), ("SDG", 2), ("SEK", 2), ("SGD", 2),
+    ("SHP", 2), ("SLE", 2), ("SOS", 2), ("SRD", 2), ("SSP", 2), ("STN", 2), ("SVC", 2), ("SYP", 2),
+    ("SZL", 2), ("THB", 2), ("TJS", 2), ("TMT", 2), ("TND", 3), ("TOP", 2), ("TRY", 2), ("TTD", 2),
+    ("TWD", 2), ("TZS", 2), ("UAH", 2), ("UGX", 0), ("USD", 2), ("UYU", 2), ("UZS", 2), ("VES", 2),
+    ("VND", 0), ("VUV", 0), ("WST", 2), ("XAF", 0), ("XCD", 2), ("XOF", 0), ("XPF", 0), ("YER", 2),
+    ("ZAR", 2), ("ZMW", 2), ("ZWG", 2),
+];
+
+impl Currency {
+    /// Looks up an ISO 4217 code, `None` for the codes the service doesn't know.
+    pub fn from_code(code: &str) -> Option<Currency> {
+        CURRENCIES
+            .binary_search_by(|(known, _)| known.cmp(&code))
+            .ok()
+            .map(|i| Currency {
+                code: CURRENCIES[i].0,
+                minor_units: CURRENCIES[i].1,
+            })
+    }
+
+    pub fn code(self) -> &'static str {
+        self.code
+    }
+
+    /// Number of digits after the decimal separator, e.g. 2 for `USD` and 0 for `JPY`.
+    pub fn minor_units(self) -> u32 {
+        self.minor_units
+    }
+
+    /// Formats the amount in the major units, e.g. `18.17 USD` for 1817 cents.
+    pub fn format(self, amount: Money) -> String {
+        let minor = amount.minor();
+        let sign = if minor < 0 { "-" } else { "" };
+        let value = minor.unsigned_abs();
+        if self.minor_units == 0 {
+            return format!("{sign}{value} {}", self.code);
+        }
+        let scale = 10u64.pow(self.minor_units);
+        format!(
+            "{sign}{}.{:0width$} {}",
+            value / scale,
+            value % scale,
+            self.code,
+            width = self.minor_units as usize,
+        )
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+/// Money in a known currency, amounts are added only within the same currency.
+///
+/// Minor units of different currencies are not comparable, e.g. cents and yens,
+/// so aggregations sum `Amount`s rather than bare [`Money`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amount {
+    money: Money,
+    currency: Currency,
+}
+
+impl Amount {
+    pub fn new(money: Money, currency: Currency) -> Amount {
+        Amount { money, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Amount {
+        Amount::new(Money::ZERO, currency)
+    }
+
+    pub fn money(self) -> Money {
+        self.money
+    }
+
+    pub fn currency(self) -> Currency {
+        self.currency
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Amount, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        let money = self
+            .money
+            .checked_add(other.money)
+            .ok_or(MoneyError::Overflow(self.currency))?;
+        Ok(Amount::new(money, self.currency))
+    }
+}
+
+/// Errors of the arithmetic on amounts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch(Currency, Currency),
+    Overflow(Currency),
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(left, right) => {
+                write!(f, "amounts in {} and {} can't be added", left, right)
+            }
+            MoneyError::Overflow(currency) => {
+                write!(f, "sum of the {} amounts overflows", currency)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MoneyError {}
@@ -0,0 +1,306 @@
+//! OpenAPI document of the JSON API and a page to browse it.
+//!
+//! The schemas are derived from the types the handlers serialize, so the document can't
+//! describe fields the API doesn't have. `tests/openapi.rs` checks it against the routes.
+
+use std::sync::LazyLock;
+
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::Value;
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme},
+    Modify, OpenApi,
+};
+
+use crate::{auth::API_KEY_HEADER, handlers, health, metrics, ui};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "view-service",
+        description = "Orders of the WBTech course. Money amounts are integers in the minor units of `payment.currency`."
+    ),
+    paths(
+        handlers::create_order,
+        handlers::get_order,
+        handlers::delete_order,
+        handlers::update_order_status,
+        handlers::order_history,
+        handlers::list_orders,
+        handlers::create_orders_batch,
+        handlers::erase_customer,
+        handlers::revenue_stats,
+        handlers::brand_stats,
+        handlers::product_stats,
+        handlers::delivery_cost_stats,
+        handlers::delivery_service_stats,
+        health::healthz,
+        health::readyz,
+        metrics::metrics,
+    ),
+    modifiers(&SecuritySchemes, &NoLicense),
+    tags(
+        (name = "orders", description = "Orders and their status history"),
+        (name = "stats", description = "Aggregates over the orders created within a range"),
+        (name = "customers", description = "Customer data management"),
+        (name = "probes", description = "Health checks and metrics, no API key is required"),
+    )
+)]
+pub struct ApiDoc;
+
+// the same key is accepted in either header, see `auth::authenticate`
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+// the package has no license, utoipa would report an empty one
+struct NoLicense;
+
+impl Modify for NoLicense {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        openapi.info.license = None;
+    }
+}
+
+// the document doesn't change while the service is running
+static SPEC: LazyLock<utoipa::openapi::OpenApi> = LazyLock::new(ApiDoc::openapi);
+
+/// Serves the OpenAPI 3 document, the fields keep the order of the struct definitions.
+pub async fn openapi_json() -> Response {
+    Json(&*SPEC).into_response()
+}
+
+#[derive(Template)]
+#[template(path = "docs.html")]
+struct DocsPage {
+    query: String,
+    title: String,
+    version: String,
+    description: String,
+    tags: Vec<TagView>,
+    schemas: Vec<SchemaView>,
+}
+
+struct TagView {
+    name: String,
+    description: String,
+    operations: Vec<OperationView>,
+}
+
+struct OperationView {
+    method: String,
+    path: String,
+    summary: String,
+    description: String,
+    secured: bool,
+    params: Vec<FieldView>,
+    // content type and schema of every accepted body
+    bodies: Vec<(String, String)>,
+    responses: Vec<ResponseView>,
+}
+
+struct ResponseView {
+    status: String,
+    description: String,
+    // `None` for the responses without a body
+    schema: Option<String>,
+}
+
+// a parameter or a schema property
+struct FieldView {
+    name: String,
+    // `query`, `header` or `path` for parameters, empty for properties
+    location: String,
+    schema: String,
+    required: bool,
+    description: String,
+}
+
+struct SchemaView {
+    name: String,
+    schema: String,
+    description: String,
+    properties: Vec<FieldView>,
+}
+
+/// Page listing the operations and schemas of the OpenAPI document, no scripts are involved.
+pub async fn docs() -> Response {
+    match serde_json::to_value(&*SPEC) {
+        Ok(spec) => ui::render(StatusCode::OK, DocsPage::new(&spec)),
+        Err(e) => {
+            tracing::error!("failed to serialize the OpenAPI document: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+impl DocsPage {
+    fn new(spec: &Value) -> DocsPage {
+        let mut tags: Vec<TagView> = spec["tags"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|tag| TagView {
+                name: text(&tag["name"]),
+                description: text(&tag["description"]),
+                operations: Vec::new(),
+            })
+            .collect();
+
+        for (path, item) in entries(&spec["paths"]) {
+            for (method, operation) in entries(item) {
+                let tag = text(&operation["tags"][0]);
+                let view = OperationView::new(path, method, operation);
+                match tags.iter_mut().find(|known| known.name == tag) {
+                    Some(known) => known.operations.push(view),
+                    None => tags.push(TagView {
+                        name: tag,
+                        description: String::new(),
+                        operations: vec![view],
+                    }),
+                }
+            }
+        }
+
+        let schemas = entries(&spec["components"]["schemas"])
+            .map(|(name, schema)| SchemaView {
+                name: name.to_string(),
+                schema: schema_name(schema),
+                description: text(&schema["description"]),
+                properties: properties(schema),
+            })
+            .collect();
+
+        DocsPage {
+            query: String::new(),
+            title: text(&spec["info"]["title"]),
+            version: text(&spec["info"]["version"]),
+            description: text(&spec["info"]["description"]),
+            tags,
+            schemas,
+        }
+    }
+}
+
+impl OperationView {
+    fn new(path: &str, method: &str, operation: &Value) -> OperationView {
+        let params = operation["parameters"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|param| FieldView {
+                name: text(&param["name"]),
+                location: text(&param["in"]),
+                schema: schema_name(&param["schema"]),
+                required: param["required"].as_bool().unwrap_or(false),
+                description: text(&param["description"]),
+            })
+            .collect();
+        let bodies = entries(&operation["requestBody"]["content"])
+            .map(|(content_type, content)| {
+                (content_type.to_string(), schema_name(&content["schema"]))
+            })
+            .collect();
+        let responses = entries(&operation["responses"])
+            .map(|(status, response)| ResponseView {
+                status: status.to_string(),
+                description: text(&response["description"]),
+                schema: entries(&response["content"])
+                    .next()
+                    .map(|(_, content)| schema_name(&content["schema"])),
+            })
+            .collect();
+
+        OperationView {
+            method: method.to_uppercase(),
+            path: path.to_string(),
+            summary: text(&operation["summary"]),
+            description: text(&operation["description"]),
+            secured: operation.get("security").is_some(),
+            params,
+            bodies,
+            responses,
+        }
+    }
+}
+
+fn properties(schema: &Value) -> Vec<FieldView> {
+    let required = schema["required"].as_array();
+    entries(&schema["properties"])
+        .map(|(name, property)| FieldView {
+            name: name.to_string(),
+            location: String::new(),
+            schema: schema_name(property),
+            required: required.is_some_and(|required| required.iter().any(|r| r == name)),
+            description: text(&property["description"]),
+        })
+        .collect()
+}
+
+// short human readable form of a schema, e.g. `[Item]` or `string (date-time)`
+fn schema_name(schema: &Value) -> String {
+    if let Some(reference) = schema["$ref"].as_str() {
+        return reference
+            .rsplit('/')
+            .next()
+            .unwrap_or(reference)
+            .to_string();
+    }
+    for combined in ["oneOf", "anyOf", "allOf"] {
+        if let Some(variants) = schema[combined].as_array() {
+            return variants
+                .iter()
+                .map(schema_name)
+                .collect::<Vec<_>>()
+                .join(" | ");
+        }
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        return values
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+
+    let name = match &schema["type"] {
+        Value::String(ty) if ty == "array" => format!("[{}]", schema_name(&schema["items"])),
+        Value::String(ty) => ty.clone(),
+        // nullable fields have the `["string", "null"]` form
+        Value::Array(types) => types.iter().map(text).collect::<Vec<_>>().join(" | "),
+        _ => "any".to_string(),
+    };
+    match schema["format"].as_str() {
+        Some(format) => format!("{name} ({format})"),
+        None => name,
+    }
+}
+
+fn entries(value: &Value) -> impl Iterator<Item = (&str, &Value)> {
+    value
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value))
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
@@ -0,0 +1,291 @@
+//! Personal data of the order recipients: role-aware masking and erasure by customer.
+
+use std::{fmt, str::FromStr, sync::Arc};
+
+use async_trait::async_trait;
+use axum::{extract::FromRequestParts, http::request::Parts};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio_postgres::Client;
+use utoipa::ToSchema;
+
+use crate::{
+    auth::ApiKey,
+    error::AppError,
+    idempotency::FieldDiff,
+    metrics,
+    schemas::{Delivery, Order},
+    AppState,
+};
+
+pub const ROLE_HEADER: &str = "x-role";
+
+// replaces the erased fields, city and region are kept for the delivery stats
+pub const ERASED: &str = "[erased]";
+
+// replaces both sides of the merged PII differences
+pub const MASKED: &str = "[masked]";
+
+/// Role of the API caller, decides whether the delivery PII is shown as is.
+///
+/// Authenticated requests get the role of their API key. Otherwise the role is taken from
+/// the `X-Role` header set by the gateway in front of the service, requests without it
+/// get the configured default role.
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // delivery name, phone, zip, address and email are masked
+    Viewer,
+    // PII is shown as is and customers may be erased
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn can_read_pii(self) -> bool {
+        self == Role::Admin
+    }
+
+    pub fn can_erase(self) -> bool {
+        self == Role::Admin
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = AppError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "viewer" => Ok(Role::Viewer),
+            "admin" => Ok(Role::Admin),
+            _ => Err(AppError::BadRequest(format!(
+                "Unknown role {value:?}, expected viewer or admin"
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for Role {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(key) = parts.extensions.get::<Arc<ApiKey>>() {
+            return Ok(key.role());
+        }
+        match parts.headers.get(ROLE_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| AppError::BadRequest("X-Role header is not a string".to_string()))?
+                .trim()
+                .parse(),
+            None => Ok(state.default_role),
+        }
+    }
+}
+
+/// Masks the delivery PII unless the role may read it.
+pub fn redact(mut order: Order, role: Role) -> Order {
+    if !role.can_read_pii() {
+        mask_delivery(&mut order.delivery);
+    }
+    order
+}
+
+// the delivery fields holding PII
+const PII_FIELDS: [&str; 5] = ["name", "phone", "zip", "address", "email"];
+
+pub fn mask_delivery(delivery: &mut Delivery) {
+    for (field, value) in [
+        ("name", &mut delivery.name),
+        ("phone", &mut delivery.phone),
+        ("zip", &mut delivery.zip),
+        ("address", &mut delivery.address),
+        ("email", &mut delivery.email),
+    ] {
+        if let Some(masked) = mask_field(field, value) {
+            *value = masked;
+        }
+    }
+}
+
+/// Masks the stored values of the PII fields in a resubmission diff.
+///
+/// Anyone may resubmit an order, so the diff never reveals the stored PII.
+pub fn mask_diff(diff: &mut [FieldDiff]) {
+    for change in diff {
+        let Some(field) = change.field.strip_prefix("delivery.") else {
+            continue;
+        };
+        if let Value::String(value) = &change.existing {
+            if let Some(masked) = mask_field(field, value) {
+                change.existing = Value::String(masked);
+            }
+        }
+    }
+}
+
+/// Merges the differences of the delivery PII into a single masked `delivery` entry unless
+/// the role may read the PII.
+///
+/// Otherwise a caller could resubmit an order with guessed values to learn which of the
+/// stored ones they match.
+pub fn redact_diff(diff: Vec<FieldDiff>, role: Role) -> Vec<FieldDiff> {
+    if role.can_read_pii() {
+        return diff;
+    }
+    let mut redacted = Vec::with_capacity(diff.len());
+    let mut merged = false;
+    for change in diff {
+        let is_pii = change
+            .field
+            .strip_prefix("delivery.")
+            .is_some_and(|field| PII_FIELDS.contains(&field));
+        if !is_pii {
+            redacted.push(change);
+        } else if !merged {
+            merged = true;
+            redacted.push(FieldDiff {
+                field: "delivery".to_string(),
+                existing: Value::String(MASKED.to_string()),
+                received: Value::String(MASKED.to_string()),
+            });
+        }
+    }
+    redacted
+}
+
+// `None` for the fields that are not PII
+fn mask_field(field: &str, value: &str) -> Option<String> {
+    if value == ERASED {
+        return Some(value.to_string());
+    }
+    let masked = match field {
+        // initials only, e.g. `T*** T*****`
+        "name" => value
+            .split(' ')
+            .map(|word| mask(word, 1, 0))
+            .collect::<Vec<_>>()
+            .join(" "),
+        // the last two digits, e.g. `+********00`
+        "phone" => match value.strip_prefix('+') {
+            Some(digits) => format!("+{}", mask(digits, 0, 2)),
+            None => mask(value, 0, 2),
+        },
+        // the domain is kept, e.g. `t***@gmail.com`
+        "email" => match value.split_once('@') {
+            Some((local, domain)) => format!("{}@{}", mask(local, 1, 0), domain),
+            None => mask(value, 0, 0),
+        },
+        "zip" | "address" => mask(value, 0, 0),
+        _ => return None,
+    };
+    Some(masked)
+}
+
+// keeps `start` first and `end` last chars, the rest is replaced with `*`
+fn mask(value: &str, start: usize, end: usize) -> String {
+    let len = value.chars().count();
+    value
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i < start || i + end >= len || c.is_whitespace() {
+                c
+            } else {
+                '*'
+            }
+        })
+        .collect()
+}
+
+/// Body of `POST /customers/:customer_id/erasure`.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct ErasureRequest {
+    pub reason: String,
+}
+
+/// Audit record of a customer erasure.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct Erasure {
+    pub id: i64,
+    pub customer_id: String,
+    // orders with the erased delivery data
+    pub order_uids: Vec<String>,
+    pub reason: String,
+    pub requested_by: Role,
+    pub erased_at: DateTime<Utc>,
+}
+
+/// Anonymizes the deliveries of all the customer orders and records the erasure in the same transaction.
+pub async fn erase_customer(
+    conn: &mut Client,
+    customer_id: &str,
+    request: &ErasureRequest,
+    role: Role,
+) -> Result<Erasure, AppError> {
+    let transaction = conn.transaction().await?;
+
+    let result = async move {
+        let mut order_uids: Vec<String> = transaction
+            .query(
+                "UPDATE deliveries d
+                SET name = $2, phone = $2, zip = $2, address = $2, email = $2
+                FROM orders o
+                WHERE o.order_uid = d.order_uid AND o.customer_id = $1
+                RETURNING d.order_uid",
+                &[&customer_id, &ERASED],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("order_uid"))
+            .collect();
+        if order_uids.is_empty() {
+            return Err(customer_not_found(customer_id));
+        }
+        order_uids.sort();
+
+        let row = transaction
+            .query_one(
+                "INSERT INTO pii_erasures (customer_id, order_uids, reason, requested_by)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, created_at",
+                &[&customer_id, &order_uids, &request.reason, &role.as_str()],
+            )
+            .await?;
+
+        transaction.commit().await?;
+        Ok(Erasure {
+            id: row.get("id"),
+            customer_id: customer_id.to_string(),
+            order_uids,
+            reason: request.reason.clone(),
+            requested_by: role,
+            erased_at: row.get("created_at"),
+        })
+    }
+    .await;
+    metrics::record_transaction(result.is_ok());
+    result
+}
+
+pub fn customer_not_found(customer_id: &str) -> AppError {
+    AppError::NotFound(format!("No orders of customer {customer_id}"))
+}
@@ -0,0 +1,503 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use chrono::{NaiveDate, Utc};
+
+use crate::{
+    auth::{ApiKey, NewApiKey},
+    db,
+    error::AppError,
+    listing::{Cursor, OrderFilter, OrderPage, OrderSummary},
+    migrations,
+    money::{Amount, Currency, Money},
+    pii::{self, Erasure, ErasureRequest, Role},
+    schemas::{Item, Order},
+    stats::{
+        self, BrandStats, DailyRevenue, DeliveryServiceStats, ProductStats, Ranking,
+        RegionDeliveryCost, StatsParams,
+    },
+    status::{self, OrderEvent, OrderHistory, OrderStatus, StatusChange},
+};
+
+use super::OrderRepository;
+
+/// Orders kept in memory, meant for tests and local experiments.
+///
+/// Follows the Postgres repository semantics: unique uids and keys, all or nothing batches
+/// and the same status transitions.
+#[derive(Default)]
+pub struct MemoryRepository {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    orders: HashMap<String, Order>,
+    // idempotency key -> order_uid
+    keys: HashMap<String, String>,
+    events: HashMap<String, Vec<OrderEvent>>,
+    last_event_id: i64,
+    erasures: Vec<Erasure>,
+    // by the key hash
+    api_keys: HashMap<Vec<u8>, ApiKey>,
+    // inserts left to fail like with an unreachable db
+    failing_inserts: usize,
+}
+
+impl MemoryRepository {
+    pub fn new() -> MemoryRepository {
+        MemoryRepository::default()
+    }
+
+    /// Makes the next `count` inserts fail with a pool timeout, e.g. to test the retries.
+    pub fn fail_inserts(&self, count: usize) {
+        self.lock().failing_inserts = count;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // every change is applied at once, so the data is consistent even after a panic
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Inner {
+    fn order(&self, order_uid: &str) -> Result<&Order, AppError> {
+        self.orders.get(order_uid).ok_or_else(db::order_not_found)
+    }
+
+    fn store(&mut self, order: &Order) {
+        let mut order = order.clone();
+        order.status = OrderStatus::Created;
+        let event = self.event(None, OrderStatus::Created, None);
+        self.events.insert(order.order_uid.clone(), vec![event]);
+        self.orders.insert(order.order_uid.clone(), order);
+    }
+
+    fn event(
+        &mut self,
+        from_status: Option<OrderStatus>,
+        to_status: OrderStatus,
+        reason: Option<String>,
+    ) -> OrderEvent {
+        self.last_event_id += 1;
+        OrderEvent {
+            id: self.last_event_id,
+            from_status,
+            to_status,
+            reason,
+            created_at: Utc::now(),
+        }
+    }
+}
+
+fn already_exists(order_uid: &str) -> AppError {
+    AppError::AlreadyExists(format!("Order {} already exists", order_uid))
+}
+
+fn matches(filter: &OrderFilter, order: &Order) -> bool {
+    let eq =
+        |expected: &Option<String>, value: &str| expected.as_deref().is_none_or(|e| e == value);
+    eq(&filter.customer_id, &order.customer_id)
+        && eq(&filter.track_number, &order.track_number)
+        && eq(&filter.delivery_service, &order.delivery_service)
+        && eq(&filter.currency, &order.payment.currency)
+        && eq(&filter.provider, &order.payment.provider)
+        && filter
+            .created_from
+            .is_none_or(|from| order.date_created >= from)
+        && filter.created_to.is_none_or(|to| order.date_created <= to)
+        && filter.status.is_none_or(|status| order.status == status)
+        && filter
+            .brand
+            .as_ref()
+            .is_none_or(|brand| order.items.iter().any(|item| &item.brand == brand))
+}
+
+// currency of a stored order payment, known as the orders are validated before being stored
+fn currency(order: &Order) -> Result<Currency, AppError> {
+    order.payment.currency().ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "order {} is paid in an unknown currency",
+            order.order_uid
+        ))
+    })
+}
+
+fn revenue<'a>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+) -> Result<Vec<DailyRevenue>, AppError> {
+    let mut days: BTreeMap<(NaiveDate, Currency), (i64, Amount)> = BTreeMap::new();
+    for order in orders.filter(|order| params.contains(order)) {
+        let currency = currency(order)?;
+        let day = order.date_created.date_naive();
+        let entry = days
+            .entry((day, currency))
+            .or_insert((0, Amount::zero(currency)));
+        entry.0 += 1;
+        entry.1 = entry
+            .1
+            .checked_add(Amount::new(order.payment.amount, currency))
+            .map_err(stats::money_error)?;
+    }
+    Ok(days
+        .into_iter()
+        .map(|((day, currency), (orders, revenue))| DailyRevenue {
+            day,
+            currency: currency.code().to_string(),
+            orders,
+            revenue: revenue.money(),
+        })
+        .collect())
+}
+
+fn top_brands<'a>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+) -> Result<Vec<BrandStats>, AppError> {
+    let top = top_items(orders, params, |item| item.brand.clone())?;
+    Ok(top
+        .into_iter()
+        .map(|group| BrandStats {
+            brand: group.key,
+            currency: group.currency.code().to_string(),
+            items: group.items,
+            total_price: group.total_price,
+        })
+        .collect())
+}
+
+fn top_products<'a>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+) -> Result<Vec<ProductStats>, AppError> {
+    let top = top_items(orders, params, |item| item.nm_id)?;
+    Ok(top
+        .into_iter()
+        .map(|group| ProductStats {
+            nm_id: group.key,
+            currency: group.currency.code().to_string(),
+            items: group.items,
+            total_price: group.total_price,
+        })
+        .collect())
+}
+
+// items of the same key sold in the same currency
+struct ItemGroup<K> {
+    key: K,
+    currency: Currency,
+    items: i64,
+    total_price: Money,
+}
+
+fn top_items<'a, K: Ord + Hash>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+    key: impl Fn(&Item) -> K,
+) -> Result<Vec<ItemGroup<K>>, AppError> {
+    let limit = params.top_limit()?;
+    let mut groups: HashMap<(K, Currency), (i64, Amount)> = HashMap::new();
+    for order in orders.filter(|order| params.contains(order)) {
+        let currency = currency(order)?;
+        for item in &order.items {
+            let entry = groups
+                .entry((key(item), currency))
+                .or_insert((0, Amount::zero(currency)));
+            entry.0 += 1;
+            entry.1 = entry
+                .1
+                .checked_add(Amount::new(item.total_price, currency))
+                .map_err(stats::money_error)?;
+        }
+    }
+
+    let mut top: Vec<_> = groups
+        .into_iter()
+        .map(|((key, currency), (items, total_price))| ItemGroup {
+            key,
+            currency,
+            items,
+            total_price: total_price.money(),
+        })
+        .collect();
+    top.sort_by(|a, b| {
+        let measures = match params.by {
+            Ranking::Items => (b.items, b.total_price).cmp(&(a.items, a.total_price)),
+            Ranking::TotalPrice => (b.total_price, b.items).cmp(&(a.total_price, a.items)),
+        };
+        measures.then_with(|| (&a.key, &a.currency).cmp(&(&b.key, &b.currency)))
+    });
+    top.truncate(limit as usize);
+    Ok(top)
+}
+
+fn delivery_costs<'a>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+) -> Result<Vec<RegionDeliveryCost>, AppError> {
+    let mut regions: BTreeMap<(String, Currency), (i64, Amount)> = BTreeMap::new();
+    for order in orders.filter(|order| params.contains(order)) {
+        let currency = currency(order)?;
+        let entry = regions
+            .entry((order.delivery.region.clone(), currency))
+            .or_insert((0, Amount::zero(currency)));
+        entry.0 += 1;
+        entry.1 = entry
+            .1
+            .checked_add(Amount::new(order.payment.delivery_cost, currency))
+            .map_err(stats::money_error)?;
+    }
+    Ok(regions
+        .into_iter()
+        .map(|((region, currency), (orders, cost))| RegionDeliveryCost {
+            region,
+            currency: currency.code().to_string(),
+            orders,
+            avg_delivery_cost: cost.money().minor() as f64 / orders as f64,
+        })
+        .collect())
+}
+
+fn delivery_services<'a>(
+    orders: impl Iterator<Item = &'a Order>,
+    params: &StatsParams,
+) -> Vec<DeliveryServiceStats> {
+    let mut services: HashMap<String, (i64, i64)> = HashMap::new();
+    for order in orders.filter(|order| params.contains(order)) {
+        let entry = services.entry(order.delivery_service.clone()).or_default();
+        entry.0 += 1;
+        entry.1 += order.items.len() as i64;
+    }
+    let mut rows: Vec<_> = services
+        .into_iter()
+        .map(|(delivery_service, (orders, items))| DeliveryServiceStats {
+            delivery_service,
+            orders,
+            items,
+        })
+        .collect();
+    rows.sort_by(|a, b| {
+        b.orders
+            .cmp(&a.orders)
+            .then_with(|| a.delivery_service.cmp(&b.delivery_service))
+    });
+    rows
+}
+
+#[async_trait]
+impl OrderRepository for MemoryRepository {
+    async fn insert(&self, order: &Order, idempotency_key: Option<&str>) -> Result<(), AppError> {
+        let mut inner = self.lock();
+        if inner.failing_inserts > 0 {
+            inner.failing_inserts -= 1;
+            return Err(AppError::PoolTimeout);
+        }
+        if inner.orders.contains_key(&order.order_uid) {
+            return Err(already_exists(&order.order_uid));
+        }
+        if let Some(key) = idempotency_key {
+            if inner.keys.contains_key(key) {
+                return Err(AppError::AlreadyExists(
+                    "Idempotency key has already been used".to_string(),
+                ));
+            }
+            inner.keys.insert(key.to_string(), order.order_uid.clone());
+        }
+        inner.store(order);
+        Ok(())
+    }
+
+    async fn insert_many(&self, orders: &[&Order]) -> Result<(), AppError> {
+        let mut inner = self.lock();
+        let mut uids = HashSet::new();
+        for order in orders {
+            if inner.orders.contains_key(&order.order_uid) || !uids.insert(&order.order_uid) {
+                return Err(already_exists(&order.order_uid));
+            }
+        }
+        for order in orders {
+            inner.store(order);
+        }
+        Ok(())
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Order, AppError> {
+        self.lock().order(order_uid).cloned()
+    }
+
+    async fn get_many(&self, order_uids: &[&str]) -> Result<Vec<Order>, AppError> {
+        let inner = self.lock();
+        Ok(order_uids
+            .iter()
+            .filter_map(|uid| inner.orders.get(*uid).cloned())
+            .collect())
+    }
+
+    async fn list(&self, filter: OrderFilter) -> Result<OrderPage, AppError> {
+        let limit = filter.page_limit()?;
+        let cursor = filter.cursor.as_deref().map(Cursor::parse).transpose()?;
+
+        let inner = self.lock();
+        let mut orders: Vec<OrderSummary> = inner
+            .orders
+            .values()
+            .filter(|order| matches(&filter, order))
+            .filter(|order| {
+                cursor.as_ref().is_none_or(|c| {
+                    (order.date_created, &order.order_uid) < (c.date_created, &c.order_uid)
+                })
+            })
+            .map(OrderSummary::from_order)
+            .collect();
+        orders.sort_by(|a, b| (b.date_created, &b.order_uid).cmp(&(a.date_created, &a.order_uid)));
+        orders.truncate(limit as usize + 1);
+        Ok(OrderPage::new(orders, limit))
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), AppError> {
+        let mut inner = self.lock();
+        inner
+            .orders
+            .remove(order_uid)
+            .ok_or_else(db::order_not_found)?;
+        inner.keys.retain(|_, uid| uid != order_uid);
+        inner.events.remove(order_uid);
+        Ok(())
+    }
+
+    async fn key_order(&self, key: &str) -> Result<Option<String>, AppError> {
+        Ok(self.lock().keys.get(key).cloned())
+    }
+
+    async fn change_status(
+        &self,
+        order_uid: &str,
+        change: &StatusChange,
+    ) -> Result<OrderEvent, AppError> {
+        let mut inner = self.lock();
+        let current = inner.order(order_uid)?.status;
+        status::check_transition(order_uid, current, change.status)?;
+
+        let event = inner.event(Some(current), change.status, change.reason.clone());
+        if let Some(order) = inner.orders.get_mut(order_uid) {
+            order.status = change.status;
+        }
+        inner
+            .events
+            .entry(order_uid.to_string())
+            .or_default()
+            .push(event.clone());
+        Ok(event)
+    }
+
+    async fn history(&self, order_uid: &str) -> Result<OrderHistory, AppError> {
+        let inner = self.lock();
+        let status = inner.order(order_uid)?.status;
+        Ok(OrderHistory {
+            order_uid: order_uid.to_string(),
+            status,
+            events: inner.events.get(order_uid).cloned().unwrap_or_default(),
+        })
+    }
+
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        request: &ErasureRequest,
+        role: Role,
+    ) -> Result<Erasure, AppError> {
+        let mut inner = self.lock();
+        let mut order_uids = Vec::new();
+        for order in inner
+            .orders
+            .values_mut()
+            .filter(|order| order.customer_id == customer_id)
+        {
+            let delivery = &mut order.delivery;
+            for field in [
+                &mut delivery.name,
+                &mut delivery.phone,
+                &mut delivery.zip,
+                &mut delivery.address,
+                &mut delivery.email,
+            ] {
+                *field = pii::ERASED.to_string();
+            }
+            order_uids.push(order.order_uid.clone());
+        }
+        if order_uids.is_empty() {
+            return Err(pii::customer_not_found(customer_id));
+        }
+        order_uids.sort();
+
+        let erasure = Erasure {
+            id: inner.erasures.len() as i64 + 1,
+            customer_id: customer_id.to_string(),
+            order_uids,
+            reason: request.reason.clone(),
+            requested_by: role,
+            erased_at: Utc::now(),
+        };
+        inner.erasures.push(erasure.clone());
+        Ok(erasure)
+    }
+
+    async fn revenue(&self, params: &StatsParams) -> Result<Vec<DailyRevenue>, AppError> {
+        revenue(self.lock().orders.values(), params)
+    }
+
+    async fn top_brands(&self, params: &StatsParams) -> Result<Vec<BrandStats>, AppError> {
+        top_brands(self.lock().orders.values(), params)
+    }
+
+    async fn top_products(&self, params: &StatsParams) -> Result<Vec<ProductStats>, AppError> {
+        top_products(self.lock().orders.values(), params)
+    }
+
+    async fn delivery_costs(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<RegionDeliveryCost>, AppError> {
+        delivery_costs(self.lock().orders.values(), params)
+    }
+
+    async fn delivery_services(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<DeliveryServiceStats>, AppError> {
+        Ok(delivery_services(self.lock().orders.values(), params))
+    }
+
+    async fn api_key(&self, key_hash: &[u8]) -> Result<Option<ApiKey>, AppError> {
+        Ok(self.lock().api_keys.get(key_hash).cloned())
+    }
+
+    async fn create_api_key(&self, key: &NewApiKey, key_hash: &[u8]) -> Result<ApiKey, AppError> {
+        let mut inner = self.lock();
+        if inner.api_keys.contains_key(key_hash) {
+            return Err(AppError::AlreadyExists(
+                "API key already exists".to_string(),
+            ));
+        }
+        let stored = ApiKey {
+            id: inner.api_keys.len() as i64 + 1,
+            name: key.name.clone(),
+            scopes: key.scopes.clone(),
+            rate_per_sec: key.rate_per_sec,
+            burst: key.burst,
+            created_at: Utc::now(),
+        };
+        inner.api_keys.insert(key_hash.to_vec(), stored.clone());
+        Ok(stored)
+    }
+
+    async fn schema_version(&self) -> Result<Option<i64>, AppError> {
+        // there is no schema to migrate
+        Ok(migrations::latest_version())
+    }
+}
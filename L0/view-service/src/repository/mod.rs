@@ -0,0 +1,94 @@
+use async_trait::async_trait;
+
+use crate::{
+    auth::{ApiKey, NewApiKey},
+    error::AppError,
+    listing::{OrderFilter, OrderPage},
+    pii::{Erasure, ErasureRequest, Role},
+    schemas::Order,
+    stats::{
+        BrandStats, DailyRevenue, DeliveryServiceStats, ProductStats, RegionDeliveryCost,
+        StatsParams,
+    },
+    status::{OrderEvent, OrderHistory, StatusChange},
+};
+
+pub mod memory;
+pub mod postgres;
+
+pub use memory::MemoryRepository;
+pub use postgres::PostgresRepository;
+
+/// Storage of the orders used by the handlers, the ingestion and the probes.
+///
+/// Postgres backs the service, the in-memory implementation lets the router run without a db.
+#[async_trait]
+pub trait OrderRepository: Send + Sync {
+    /// Stores the order along with the idempotency key.
+    ///
+    /// An already used `order_uid` or key is reported with `AppError::is_unique_violation`.
+    async fn insert(&self, order: &Order, idempotency_key: Option<&str>) -> Result<(), AppError>;
+
+    /// Stores either all the orders or none of them.
+    async fn insert_many(&self, orders: &[&Order]) -> Result<(), AppError>;
+
+    async fn get(&self, order_uid: &str) -> Result<Order, AppError>;
+
+    /// Returns the stored orders among the requested ones, missing orders are skipped.
+    async fn get_many(&self, order_uids: &[&str]) -> Result<Vec<Order>, AppError>;
+
+    async fn list(&self, filter: OrderFilter) -> Result<OrderPage, AppError>;
+
+    /// Removes the order with all its parts, idempotency keys and history.
+    async fn delete(&self, order_uid: &str) -> Result<(), AppError>;
+
+    /// Returns the uid of the order created with the idempotency key, if any.
+    async fn key_order(&self, key: &str) -> Result<Option<String>, AppError>;
+
+    async fn change_status(
+        &self,
+        order_uid: &str,
+        change: &StatusChange,
+    ) -> Result<OrderEvent, AppError>;
+
+    async fn history(&self, order_uid: &str) -> Result<OrderHistory, AppError>;
+
+    /// Anonymizes the delivery data of all the customer orders and records the erasure.
+    ///
+    /// A customer without orders is reported with `AppError::NotFound`.
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        request: &ErasureRequest,
+        role: Role,
+    ) -> Result<Erasure, AppError>;
+
+    async fn revenue(&self, params: &StatsParams) -> Result<Vec<DailyRevenue>, AppError>;
+
+    async fn top_brands(&self, params: &StatsParams) -> Result<Vec<BrandStats>, AppError>;
+
+    async fn top_products(&self, params: &StatsParams) -> Result<Vec<ProductStats>, AppError>;
+
+    async fn delivery_costs(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<RegionDeliveryCost>, AppError>;
+
+    async fn delivery_services(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<DeliveryServiceStats>, AppError>;
+
+    /// Looks up a not revoked API key by the SHA-256 of the key.
+    async fn api_key(&self, key_hash: &[u8]) -> Result<Option<ApiKey>, AppError>;
+
+    async fn create_api_key(&self, key: &NewApiKey, key_hash: &[u8]) -> Result<ApiKey, AppError>;
+
+    /// Checks that the storage is reachable and returns the applied migration version.
+    async fn schema_version(&self) -> Result<Option<i64>, AppError>;
+
+    /// State of the connection pool, if the storage has one.
+    fn pool_state(&self) -> Option<bb8::State> {
+        None
+    }
+}
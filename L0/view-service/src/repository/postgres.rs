@@ -0,0 +1,180 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+
+use crate::{
+    auth::{self, ApiKey, NewApiKey},
+    db,
+    error::AppError,
+    listing::{self, OrderFilter, OrderPage},
+    metrics, migrations,
+    pii::{self, Erasure, ErasureRequest, Role},
+    schemas::Order,
+    stats::{
+        self, BrandStats, DailyRevenue, DeliveryServiceStats, ProductStats, RegionDeliveryCost,
+        StatsParams,
+    },
+    status::{self, OrderEvent, OrderHistory, StatusChange},
+    DbPool,
+};
+
+use super::OrderRepository;
+
+/// Orders stored in Postgres, every call checks out a connection from the pool.
+pub struct PostgresRepository {
+    pool: DbPool,
+}
+
+impl PostgresRepository {
+    pub fn new(pool: DbPool) -> PostgresRepository {
+        PostgresRepository { pool }
+    }
+}
+
+#[async_trait]
+impl OrderRepository for PostgresRepository {
+    async fn insert(&self, order: &Order, idempotency_key: Option<&str>) -> Result<(), AppError> {
+        let mut conn = self.pool.get().await?;
+        db::inser_order_tx(&mut conn, order, idempotency_key).await
+    }
+
+    async fn insert_many(&self, orders: &[&Order]) -> Result<(), AppError> {
+        let mut conn = self.pool.get().await?;
+        let transaction = conn.transaction().await?;
+        if let Err(e) = db::insert_orders(&transaction, orders).await {
+            metrics::record_transaction(false);
+            return Err(e);
+        }
+        let committed = transaction.commit().await;
+        metrics::record_transaction(committed.is_ok());
+        Ok(committed?)
+    }
+
+    async fn get(&self, order_uid: &str) -> Result<Order, AppError> {
+        let conn = self.pool.get().await?;
+        db::collect_order(&conn, order_uid).await
+    }
+
+    async fn get_many(&self, order_uids: &[&str]) -> Result<Vec<Order>, AppError> {
+        let conn = self.pool.get().await?;
+        // most of the requested orders are usually new, so only the stored ones are collected
+        let stored: HashSet<String> = conn
+            .query(
+                "SELECT order_uid FROM orders WHERE order_uid = ANY($1)",
+                &[&order_uids],
+            )
+            .await?
+            .iter()
+            .map(|row| row.get("order_uid"))
+            .collect();
+
+        let mut orders = Vec::with_capacity(stored.len());
+        for order_uid in order_uids.iter().filter(|uid| stored.contains(**uid)) {
+            orders.push(db::collect_order(&conn, order_uid).await?);
+        }
+        Ok(orders)
+    }
+
+    async fn list(&self, filter: OrderFilter) -> Result<OrderPage, AppError> {
+        let conn = self.pool.get().await?;
+        listing::list_orders(&conn, filter).await
+    }
+
+    async fn delete(&self, order_uid: &str) -> Result<(), AppError> {
+        let conn = self.pool.get().await?;
+        // the order parts, keys and events are removed by the foreign key cascades
+        let deleted = conn
+            .execute("DELETE FROM orders WHERE order_uid = $1", &[&order_uid])
+            .await?;
+        if deleted == 0 {
+            return Err(db::order_not_found());
+        }
+        Ok(())
+    }
+
+    async fn key_order(&self, key: &str) -> Result<Option<String>, AppError> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "SELECT order_uid FROM idempotency_keys WHERE idempotency_key = $1",
+                &[&key],
+            )
+            .await?;
+        Ok(row.map(|row| row.get("order_uid")))
+    }
+
+    async fn change_status(
+        &self,
+        order_uid: &str,
+        change: &StatusChange,
+    ) -> Result<OrderEvent, AppError> {
+        let mut conn = self.pool.get().await?;
+        status::change_status(&mut conn, order_uid, change).await
+    }
+
+    async fn history(&self, order_uid: &str) -> Result<OrderHistory, AppError> {
+        let conn = self.pool.get().await?;
+        status::order_history(&conn, order_uid).await
+    }
+
+    async fn erase_customer(
+        &self,
+        customer_id: &str,
+        request: &ErasureRequest,
+        role: Role,
+    ) -> Result<Erasure, AppError> {
+        let mut conn = self.pool.get().await?;
+        pii::erase_customer(&mut conn, customer_id, request, role).await
+    }
+
+    async fn revenue(&self, params: &StatsParams) -> Result<Vec<DailyRevenue>, AppError> {
+        let conn = self.pool.get().await?;
+        stats::revenue(&conn, params).await
+    }
+
+    async fn top_brands(&self, params: &StatsParams) -> Result<Vec<BrandStats>, AppError> {
+        let conn = self.pool.get().await?;
+        stats::top_brands(&conn, params).await
+    }
+
+    async fn top_products(&self, params: &StatsParams) -> Result<Vec<ProductStats>, AppError> {
+        let conn = self.pool.get().await?;
+        stats::top_products(&conn, params).await
+    }
+
+    async fn delivery_costs(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<RegionDeliveryCost>, AppError> {
+        let conn = self.pool.get().await?;
+        stats::delivery_costs(&conn, params).await
+    }
+
+    async fn delivery_services(
+        &self,
+        params: &StatsParams,
+    ) -> Result<Vec<DeliveryServiceStats>, AppError> {
+        let conn = self.pool.get().await?;
+        stats::delivery_services(&conn, params).await
+    }
+
+    async fn api_key(&self, key_hash: &[u8]) -> Result<Option<ApiKey>, AppError> {
+        let conn = self.pool.get().await?;
+        auth::find_key(&conn, key_hash).await
+    }
+
+    async fn create_api_key(&self, key: &NewApiKey, key_hash: &[u8]) -> Result<ApiKey, AppError> {
+        let conn = self.pool.get().await?;
+        auth::insert_key(&conn, key, key_hash).await
+    }
+
+    async fn schema_version(&self) -> Result<Option<i64>, AppError> {
+        let conn = self.pool.get().await?;
+        conn.simple_query("SELECT 1").await?;
+        Ok(migrations::current_version(&conn).await?)
+    }
+
+    fn pool_state(&self) -> Option<bb8::State> {
+        Some(self.pool.state())
+    }
+}
@@ -1,14 +1,21 @@
 use serde::{Deserialize, Serialize};
 use tokio_postgres::Row;
+use utoipa::ToSchema;
 
 use chrono::{DateTime, Utc};
 
+use crate::{
+    money::{Currency, Money},
+    status::OrderStatus,
+};
+
 // In bigger projects it's much more easier to use some ORM solution
 // that manages field serialization and deserialization for sql queries
 
-// Chose bigints(i64) for numerical values as I don't really know what range is the most suitable
+// Chose bigints(i64) for numerical values as I don't really know what range is the most suitable,
+// money amounts are bigints as well, counted in the minor units of the payment currency
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct Order {
     pub order_uid: String,
     pub track_number: String,
@@ -24,6 +31,9 @@ pub struct Order {
     pub sm_id: i64,
     pub date_created: DateTime<Utc>,
     pub oof_shard: String,
+    // managed by the service, the status of a received order is ignored
+    #[serde(default)]
+    pub status: OrderStatus,
 }
 
 impl Order {
@@ -43,11 +53,12 @@ impl Order {
             sm_id: row.get("sm_id"),
             date_created: row.get("date_created"),
             oof_shard: row.get("oof_shard"),
+            status: row.get("status"),
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct Delivery {
     pub name: String,
     pub phone: String,
@@ -72,21 +83,26 @@ impl Delivery {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct Payment {
     pub transaction: String,
     pub request_id: String,
     pub currency: String,
     pub provider: String,
-    pub amount: i64,
+    pub amount: Money,
     pub payment_dt: i64,
     pub bank: String,
-    pub delivery_cost: i64,
-    pub goods_total: i64,
-    pub custom_fee: i64,
+    pub delivery_cost: Money,
+    pub goods_total: Money,
+    pub custom_fee: Money,
 }
 
 impl Payment {
+    /// Currency of all the order amounts, `None` for an unknown code.
+    pub fn currency(&self) -> Option<Currency> {
+        Currency::from_code(&self.currency)
+    }
+
     pub fn from_row(row: &Row) -> Payment {
         Payment {
             transaction: row.get("transaction_id"),
@@ -103,16 +119,16 @@ impl Payment {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Default)]
+#[derive(Serialize, Deserialize, Clone, Default, ToSchema)]
 pub struct Item {
     pub chrt_id: i64,
     pub track_number: String,
-    pub price: i64,
+    pub price: Money,
     pub rid: String,
     pub name: String,
     pub sale: i64,
     pub size: String,
-    pub total_price: i64,
+    pub total_price: Money,
     pub nm_id: i64,
     pub brand: String,
     pub status: i64,
@@ -0,0 +1,279 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{error::SqlState, Client, Row};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    error::AppError,
+    money::{Money, MoneyError},
+    schemas::Order,
+};
+
+pub const DEFAULT_TOP_SIZE: i64 = 10;
+pub const MAX_TOP_SIZE: i64 = 100;
+
+// optional inclusive bounds of `date_created`, shared by all the stats queries
+const RANGE_CONDITION: &str = "($1::timestamptz IS NULL OR o.date_created >= $1)
+    AND ($2::timestamptz IS NULL OR o.date_created <= $2)";
+
+/// Query parameters of the stats, only orders created within the range are counted.
+#[derive(Deserialize, Debug, Default, Clone, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatsParams {
+    // inclusive bounds of `date_created`
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    // size of the top lists
+    pub limit: Option<i64>,
+    #[serde(default)]
+    pub by: Ranking,
+}
+
+/// Measure the top lists are sorted by.
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Ranking {
+    // amount of sold items
+    #[default]
+    Items,
+    TotalPrice,
+}
+
+impl StatsParams {
+    pub fn top_limit(&self) -> Result<i64, AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_TOP_SIZE);
+        if !(1..=MAX_TOP_SIZE).contains(&limit) {
+            return Err(AppError::BadRequest(format!(
+                "limit must be between 1 and {MAX_TOP_SIZE}"
+            )));
+        }
+        Ok(limit)
+    }
+
+    /// Whether the order is created within the range.
+    pub fn contains(&self, order: &Order) -> bool {
+        self.created_from
+            .is_none_or(|from| order.date_created >= from)
+            && self.created_to.is_none_or(|to| order.date_created <= to)
+    }
+}
+
+/// Error of the money sums that don't fit into an amount, a narrower range may still fit.
+pub fn overflow() -> AppError {
+    AppError::BadRequest(
+        "amounts of the orders within the range overflow, narrow the created range".to_string(),
+    )
+}
+
+/// Error of the amounts that can't be summed, the overflows are reported as [`overflow`].
+pub fn money_error(e: MoneyError) -> AppError {
+    match e {
+        MoneyError::Overflow(_) => overflow(),
+        e => AppError::BadRequest(e.to_string()),
+    }
+}
+
+// the `::bigint` casts of the sums fail with the same overflow as the checked sums in memory
+fn sum_error(e: tokio_postgres::Error) -> AppError {
+    match e.code() {
+        Some(code) if *code == SqlState::NUMERIC_VALUE_OUT_OF_RANGE => overflow(),
+        _ => e.into(),
+    }
+}
+
+/// Response of every stats endpoint.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct Stats<T> {
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub rows: Vec<T>,
+}
+
+impl<T> Stats<T> {
+    pub fn new(params: &StatsParams, rows: Vec<T>) -> Stats<T> {
+        Stats {
+            created_from: params.created_from,
+            created_to: params.created_to,
+            rows,
+        }
+    }
+}
+
+/// Payments of the orders created on the day (UTC), amounts in different currencies are not summed.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct DailyRevenue {
+    pub day: NaiveDate,
+    pub currency: String,
+    pub orders: i64,
+    pub revenue: Money,
+}
+
+/// Items of the brand sold in the currency, amounts in different currencies are not summed.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct BrandStats {
+    pub brand: String,
+    pub currency: String,
+    pub items: i64,
+    pub total_price: Money,
+}
+
+/// Items of the product sold in the currency, amounts in different currencies are not summed.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct ProductStats {
+    pub nm_id: i64,
+    pub currency: String,
+    pub items: i64,
+    pub total_price: Money,
+}
+
+/// Delivery costs of the region paid in the currency, different currencies are not averaged.
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct RegionDeliveryCost {
+    pub region: String,
+    pub currency: String,
+    pub orders: i64,
+    pub avg_delivery_cost: f64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, ToSchema)]
+pub struct DeliveryServiceStats {
+    pub delivery_service: String,
+    pub orders: i64,
+    pub items: i64,
+}
+
+pub async fn revenue(conn: &Client, params: &StatsParams) -> Result<Vec<DailyRevenue>, AppError> {
+    let query = format!(
+        "SELECT
+            (o.date_created AT TIME ZONE 'UTC')::date AS day, p.currency,
+            count(*) AS orders, COALESCE(sum(p.amount), 0)::bigint AS revenue
+        FROM orders o
+        JOIN payments p ON p.order_uid = o.order_uid
+        WHERE {RANGE_CONDITION}
+        GROUP BY day, p.currency
+        ORDER BY day, p.currency"
+    );
+    let rows = conn
+        .query(&query, &[&params.created_from, &params.created_to])
+        .await
+        .map_err(sum_error)?;
+    Ok(rows
+        .iter()
+        .map(|row| DailyRevenue {
+            day: row.get("day"),
+            currency: row.get("currency"),
+            orders: row.get("orders"),
+            revenue: row.get("revenue"),
+        })
+        .collect())
+}
+
+pub async fn top_brands(conn: &Client, params: &StatsParams) -> Result<Vec<BrandStats>, AppError> {
+    let rows = top_items(conn, params, "i.brand").await?;
+    Ok(rows
+        .iter()
+        .map(|row| BrandStats {
+            brand: row.get("key"),
+            currency: row.get("currency"),
+            items: row.get("items"),
+            total_price: row.get("total_price"),
+        })
+        .collect())
+}
+
+pub async fn top_products(
+    conn: &Client,
+    params: &StatsParams,
+) -> Result<Vec<ProductStats>, AppError> {
+    let rows = top_items(conn, params, "i.nm_id").await?;
+    Ok(rows
+        .iter()
+        .map(|row| ProductStats {
+            nm_id: row.get("key"),
+            currency: row.get("currency"),
+            items: row.get("items"),
+            total_price: row.get("total_price"),
+        })
+        .collect())
+}
+
+// items grouped by the `key` column and the payment currency,
+// ties are broken by the other measure, the key and the currency
+async fn top_items(conn: &Client, params: &StatsParams, key: &str) -> Result<Vec<Row>, AppError> {
+    let limit = params.top_limit()?;
+    let order = match params.by {
+        Ranking::Items => "items DESC, total_price DESC",
+        Ranking::TotalPrice => "total_price DESC, items DESC",
+    };
+    let query = format!(
+        "SELECT
+            {key} AS key, p.currency,
+            count(*) AS items, COALESCE(sum(i.total_price), 0)::bigint AS total_price
+        FROM items i
+        JOIN orders o ON o.order_uid = i.order_uid
+        JOIN payments p ON p.order_uid = o.order_uid
+        WHERE {RANGE_CONDITION}
+        GROUP BY {key}, p.currency
+        ORDER BY {order}, key, p.currency
+        LIMIT $3"
+    );
+    conn.query(&query, &[&params.created_from, &params.created_to, &limit])
+        .await
+        .map_err(sum_error)
+}
+
+pub async fn delivery_costs(
+    conn: &Client,
+    params: &StatsParams,
+) -> Result<Vec<RegionDeliveryCost>, AppError> {
+    let query = format!(
+        "SELECT
+            d.region, p.currency, count(*) AS orders,
+            sum(p.delivery_cost)::bigint::float8 / count(*) AS avg_delivery_cost
+        FROM orders o
+        JOIN deliveries d ON d.order_uid = o.order_uid
+        JOIN payments p ON p.order_uid = o.order_uid
+        WHERE {RANGE_CONDITION}
+        GROUP BY d.region, p.currency
+        ORDER BY d.region, p.currency"
+    );
+    let rows = conn
+        .query(&query, &[&params.created_from, &params.created_to])
+        .await
+        .map_err(sum_error)?;
+    Ok(rows
+        .iter()
+        .map(|row| RegionDeliveryCost {
+            region: row.get("region"),
+            currency: row.get("currency"),
+            orders: row.get("orders"),
+            avg_delivery_cost: row.get("avg_delivery_cost"),
+        })
+        .collect())
+}
+
+pub async fn delivery_services(
+    conn: &Client,
+    params: &StatsParams,
+) -> Result<Vec<DeliveryServiceStats>, AppError> {
+    let query = format!(
+        "SELECT
+            o.delivery_service, count(DISTINCT o.order_uid) AS orders, count(i.order_uid) AS items
+        FROM orders o
+        LEFT JOIN items i ON i.order_uid = o.order_uid
+        WHERE {RANGE_CONDITION}
+        GROUP BY o.delivery_service
+        ORDER BY orders DESC, o.delivery_service"
+    );
+    let rows = conn
+        .query(&query, &[&params.created_from, &params.created_to])
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| DeliveryServiceStats {
+            delivery_service: row.get("delivery_service"),
+            orders: row.get("orders"),
+            items: row.get("items"),
+        })
+        .collect())
+}
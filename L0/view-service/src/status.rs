@@ -0,0 +1,237 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{
+    types::{FromSql, Type},
+    Client, Row,
+};
+use utoipa::ToSchema;
+
+use crate::{db, error::AppError, metrics};
+
+/// Lifecycle status of an order, new orders are always `created`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    #[default]
+    Created,
+    Paid,
+    Shipped,
+    Delivered,
+    Cancelled,
+}
+
+impl OrderStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OrderStatus::Created => "created",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+        }
+    }
+
+    /// Statuses the order may be moved to, delivered and cancelled orders are final.
+    pub fn transitions(self) -> &'static [OrderStatus] {
+        match self {
+            OrderStatus::Created => &[OrderStatus::Paid, OrderStatus::Cancelled],
+            OrderStatus::Paid => &[OrderStatus::Shipped, OrderStatus::Cancelled],
+            OrderStatus::Shipped => &[OrderStatus::Delivered],
+            OrderStatus::Delivered | OrderStatus::Cancelled => &[],
+        }
+    }
+
+    pub fn can_become(self, next: OrderStatus) -> bool {
+        self.transitions().contains(&next)
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownStatus(String);
+
+impl fmt::Display for UnknownStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown order status: {}", self.0)
+    }
+}
+
+impl Error for UnknownStatus {}
+
+impl FromStr for OrderStatus {
+    type Err = UnknownStatus;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(OrderStatus::Created),
+            "paid" => Ok(OrderStatus::Paid),
+            "shipped" => Ok(OrderStatus::Shipped),
+            "delivered" => Ok(OrderStatus::Delivered),
+            "cancelled" => Ok(OrderStatus::Cancelled),
+            other => Err(UnknownStatus(other.to_string())),
+        }
+    }
+}
+
+// statuses are stored as plain strings
+impl<'a> FromSql<'a> for OrderStatus {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        let value = <&str as FromSql>::from_sql(ty, raw)?;
+        Ok(value.parse()?)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <&str as FromSql>::accepts(ty)
+    }
+}
+
+/// Body of `PATCH /order/:order_uid/status`.
+#[derive(Deserialize, Debug, ToSchema)]
+pub struct StatusChange {
+    pub status: OrderStatus,
+    pub reason: Option<String>,
+}
+
+/// Response of `PATCH /order/:order_uid/status`.
+#[derive(Serialize, Debug, ToSchema)]
+pub struct StatusUpdate {
+    pub order_uid: String,
+    pub status: OrderStatus,
+    pub event: OrderEvent,
+}
+
+/// Entry of the append-only order history.
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct OrderEvent {
+    pub id: i64,
+    // `None` for the creation of the order
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OrderEvent {
+    fn from_row(row: &Row) -> OrderEvent {
+        OrderEvent {
+            id: row.get("id"),
+            from_status: row.get("from_status"),
+            to_status: row.get("to_status"),
+            reason: row.get("reason"),
+            created_at: row.get("created_at"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct OrderHistory {
+    pub order_uid: String,
+    pub status: OrderStatus,
+    // oldest first
+    pub events: Vec<OrderEvent>,
+}
+
+/// Rejects a change to a status not reachable from the current one.
+pub fn check_transition(
+    order_uid: &str,
+    current: OrderStatus,
+    next: OrderStatus,
+) -> Result<(), AppError> {
+    if current.can_become(next) {
+        return Ok(());
+    }
+    Err(AppError::Conflict {
+        message: format!("Order status can't be changed from {} to {}", current, next),
+        details: Some(serde_json::json!({
+            "order_uid": order_uid,
+            "status": current,
+            "allowed": current.transitions(),
+        })),
+    })
+}
+
+/// Moves the order to the next status and records the event in the same transaction.
+///
+/// The order row is locked, so concurrent changes of the same order are applied one by one.
+pub async fn change_status(
+    conn: &mut Client,
+    order_uid: &str,
+    change: &StatusChange,
+) -> Result<OrderEvent, AppError> {
+    let transaction = conn.transaction().await?;
+
+    let result = async move {
+        let current: OrderStatus = transaction
+            .query_opt(
+                "SELECT status FROM orders WHERE order_uid = $1 FOR UPDATE",
+                &[&order_uid],
+            )
+            .await?
+            .ok_or_else(db::order_not_found)?
+            .get("status");
+
+        check_transition(order_uid, current, change.status)?;
+
+        transaction
+            .execute(
+                "UPDATE orders SET status = $2 WHERE order_uid = $1",
+                &[&order_uid, &change.status.as_str()],
+            )
+            .await?;
+        let row = transaction
+            .query_one(
+                "INSERT INTO order_events (order_uid, from_status, to_status, reason)
+                VALUES ($1, $2, $3, $4)
+                RETURNING id, from_status, to_status, reason, created_at",
+                &[
+                    &order_uid,
+                    &current.as_str(),
+                    &change.status.as_str(),
+                    &change.reason,
+                ],
+            )
+            .await?;
+
+        transaction.commit().await?;
+        Ok(OrderEvent::from_row(&row))
+    }
+    .await;
+    metrics::record_transaction(result.is_ok());
+    result
+}
+
+/// Returns the current status of the order along with all its events.
+pub async fn order_history(conn: &Client, order_uid: &str) -> Result<OrderHistory, AppError> {
+    let status: OrderStatus = conn
+        .query_opt(
+            "SELECT status FROM orders WHERE order_uid = $1",
+            &[&order_uid],
+        )
+        .await?
+        .ok_or_else(db::order_not_found)?
+        .get("status");
+
+    let events = conn
+        .query(
+            "SELECT id, from_status, to_status, reason, created_at
+            FROM order_events WHERE order_uid = $1 ORDER BY id",
+            &[&order_uid],
+        )
+        .await?
+        .iter()
+        .map(OrderEvent::from_row)
+        .collect();
+
+    Ok(OrderHistory {
+        order_uid: order_uid.to_string(),
+        status,
+        events,
+    })
+}
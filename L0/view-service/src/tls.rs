@@ -0,0 +1,199 @@
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use serde::Deserialize;
+use tokio_postgres::config::SslMode as PgSslMode;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+/// TLS mode of the db connections, named after the libpq `sslmode` values.
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SslMode {
+    // plain connections, e.g. for the local docker compose db
+    #[default]
+    Disable,
+    // TLS if the server supports it, the certificate is not verified
+    Prefer,
+    // TLS only, the certificate is not verified
+    Require,
+    // TLS only, the certificate chain and the host name are verified
+    VerifyFull,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    pub mode: SslMode,
+    // PEM file with the CA certificates, the Mozilla root certificates are used if not set
+    pub ca_file: Option<PathBuf>,
+    // PEM files with the client certificate chain and its private key
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read(PathBuf, io::Error),
+    // the file contains no PEM items of the expected kind
+    Empty(PathBuf, &'static str),
+    // only one of the client certificate and key is given
+    IncompleteClientAuth,
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            TlsError::Empty(path, kind) => write!(f, "no {} found in {}", kind, path.display()),
+            TlsError::IncompleteClientAuth => {
+                write!(f, "client certificate and key have to be provided together")
+            }
+            TlsError::Rustls(e) => write!(f, "invalid TLS configuration: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TlsError {}
+
+impl From<rustls::Error> for TlsError {
+    fn from(err: rustls::Error) -> Self {
+        TlsError::Rustls(err)
+    }
+}
+
+impl SslMode {
+    /// Mode matching the one parsed by the postgres driver, e.g. from a connection URL.
+    pub fn from_pg_mode(mode: PgSslMode) -> SslMode {
+        match mode {
+            PgSslMode::Disable => SslMode::Disable,
+            PgSslMode::Require => SslMode::Require,
+            _ => SslMode::Prefer,
+        }
+    }
+
+    /// Mode the postgres driver is configured with, the certificate checks are up to the connector.
+    pub fn pg_mode(self) -> PgSslMode {
+        match self {
+            SslMode::Disable => PgSslMode::Disable,
+            SslMode::Prefer => PgSslMode::Prefer,
+            SslMode::Require | SslMode::VerifyFull => PgSslMode::Require,
+        }
+    }
+}
+
+/// Builds the TLS connector for the db pool.
+///
+/// With `SslMode::Disable` the connector is never used by the driver.
+pub fn make_connector(options: &TlsOptions) -> Result<MakeRustlsConnect, TlsError> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = if options.mode == SslMode::VerifyFull {
+        let mut roots = RootCertStore::empty();
+        match &options.ca_file {
+            Some(path) => {
+                let certs = read_certs(path)?;
+                let (added, ignored) = roots.add_parsable_certificates(certs);
+                if added == 0 {
+                    return Err(TlsError::Empty(path.clone(), "valid CA certificates"));
+                }
+                if ignored > 0 {
+                    tracing::warn!("{} certificates in {} are ignored", ignored, path.display());
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        builder.with_root_certificates(roots)
+    } else {
+        // like libpq `prefer` and `require` modes the traffic is encrypted without authenticating the server
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoServerVerification(provider)))
+    };
+
+    let config = match (&options.client_cert, &options.client_key) {
+        (Some(cert), Some(key)) => {
+            builder.with_client_auth_cert(read_certs(cert)?, read_key(key)?)?
+        }
+        (None, None) => builder.with_no_client_auth(),
+        _ => return Err(TlsError::IncompleteClientAuth),
+    };
+    Ok(MakeRustlsConnect::new(config))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let pem = fs::read(path).map_err(|e| TlsError::Read(path.to_path_buf(), e))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| TlsError::Read(path.to_path_buf(), e))?;
+    if certs.is_empty() {
+        return Err(TlsError::Empty(path.to_path_buf(), "certificates"));
+    }
+    Ok(certs)
+}
+
+fn read_key(path: &Path) -> Result<PrivateKeyDer<'static>, TlsError> {
+    let pem = fs::read(path).map_err(|e| TlsError::Read(path.to_path_buf(), e))?;
+    rustls_pemfile::private_key(&mut pem.as_slice())
+        .map_err(|e| TlsError::Read(path.to_path_buf(), e))?
+        .ok_or_else(|| TlsError::Empty(path.to_path_buf(), "private key"))
+}
+
+// Accepts any server certificate while still checking the handshake signatures.
+#[derive(Debug)]
+struct NoServerVerification(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for NoServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
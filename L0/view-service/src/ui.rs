@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+};
+use chrono::DateTime;
+use serde::Deserialize;
+
+use crate::{
+    error::AppError,
+    handlers,
+    money::Money,
+    pii::{self, Role},
+    request_id,
+    schemas::Order,
+    AppState,
+};
+
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S UTC";
+
+#[derive(Deserialize, Debug)]
+pub struct OrderQuery {
+    #[serde(default)]
+    pub order_uid: String,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexPage {
+    query: String,
+}
+
+#[derive(Template)]
+#[template(path = "order.html")]
+struct OrderPage {
+    query: String,
+    order: Order,
+    date_created: String,
+    payment_time: String,
+    // sums over the items
+    items_price: Money,
+    items_total: Money,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorPage {
+    query: String,
+    title: String,
+    message: String,
+}
+
+/// Start page with the order search box.
+pub async fn index() -> Response {
+    render(
+        StatusCode::OK,
+        IndexPage {
+            query: String::new(),
+        },
+    )
+}
+
+/// Order page, the order is looked up the same way as by `GET /order/:order_uid`.
+pub async fn order_page(
+    State(state): State<Arc<AppState>>,
+    role: Role,
+    Query(params): Query<OrderQuery>,
+) -> Response {
+    let order_uid = params.order_uid.trim().to_string();
+    if order_uid.is_empty() {
+        return index().await;
+    }
+
+    match handlers::find_order(&state, &order_uid).await {
+        Ok(order) => render(
+            StatusCode::OK,
+            OrderPage::new(order_uid, pii::redact(order, role)),
+        ),
+        Err(err) => error_page(order_uid, err),
+    }
+}
+
+impl OrderPage {
+    fn new(query: String, order: Order) -> OrderPage {
+        let payment_time = DateTime::from_timestamp(order.payment.payment_dt, 0)
+            .map(|dt| dt.format(TIME_FORMAT).to_string())
+            .unwrap_or_else(|| order.payment.payment_dt.to_string());
+        OrderPage {
+            query,
+            date_created: order.date_created.format(TIME_FORMAT).to_string(),
+            payment_time,
+            // stored orders are validated, so the sums fit
+            items_price: Money::checked_sum(order.items.iter().map(|item| item.price))
+                .unwrap_or_default(),
+            items_total: Money::checked_sum(order.items.iter().map(|item| item.total_price))
+                .unwrap_or_default(),
+            order,
+        }
+    }
+
+    // amounts are shown in the major units of the payment currency
+    fn money(&self, amount: &Money) -> String {
+        match self.order.payment.currency() {
+            Some(currency) => currency.format(*amount),
+            None => format!("{} {}", amount, self.order.payment.currency),
+        }
+    }
+}
+
+fn error_page(query: String, err: AppError) -> Response {
+    let status = err.status();
+    if status.is_server_error() {
+        tracing::error!("order page failed: {}", err);
+    }
+    let title = match status {
+        StatusCode::NOT_FOUND => "Order not found".to_string(),
+        _ => status.canonical_reason().unwrap_or("Error").to_string(),
+    };
+    let mut message = err.public_message();
+    if let Some(id) = request_id::current() {
+        message = format!("{} (request id: {})", message, id);
+    }
+    render(
+        status,
+        ErrorPage {
+            query,
+            title,
+            message,
+        },
+    )
+}
+
+pub(crate) fn render(status: StatusCode, page: impl Template) -> Response {
+    match page.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            tracing::error!("failed to render a page: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to render the page",
+            )
+                .into_response()
+        }
+    }
+}
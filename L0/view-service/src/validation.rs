@@ -0,0 +1,200 @@
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    money::Money,
+    schemas::{Delivery, Item, Order, Payment},
+};
+
+/// Single problem found in an order payload.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    // path to the field, e.g. `items[0].price`
+    pub field: String,
+    pub message: String,
+}
+
+/// Checks the whole order and collects all the found problems at once.
+pub fn validate_order(order: &Order) -> Result<(), Vec<FieldError>> {
+    let mut v = Validator::default();
+    v.order(order);
+    if v.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(v.errors)
+    }
+}
+
+#[derive(Default)]
+struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    fn order(&mut self, order: &Order) {
+        self.not_empty("order_uid", &order.order_uid);
+        self.not_empty("track_number", &order.track_number);
+        self.not_empty("entry", &order.entry);
+        self.not_empty("locale", &order.locale);
+        self.not_empty("customer_id", &order.customer_id);
+        self.not_empty("delivery_service", &order.delivery_service);
+        self.non_negative("sm_id", order.sm_id);
+
+        self.delivery("delivery", &order.delivery);
+        self.payment("payment", &order.payment);
+
+        if order.items.is_empty() {
+            self.error("items", "order must contain at least one item");
+        }
+        for (i, item) in order.items.iter().enumerate() {
+            let path = format!("items[{i}]");
+            self.item(&path, item);
+            if item.track_number != order.track_number {
+                self.error(
+                    format!("{path}.track_number"),
+                    "must match the order track_number",
+                );
+            }
+        }
+
+        // totals are checked only when the parts themselves are sane
+        let goods_total = Money::checked_sum(order.items.iter().map(|item| item.total_price));
+        match goods_total {
+            Some(total) if total != order.payment.goods_total => self.error(
+                "payment.goods_total",
+                format!("must be equal to the sum of items total_price ({total})"),
+            ),
+            Some(_) => {}
+            None => self.error("items", "sum of items total_price overflows"),
+        }
+    }
+
+    fn delivery(&mut self, path: &str, delivery: &Delivery) {
+        self.not_empty(format!("{path}.name"), &delivery.name);
+        if !is_phone(&delivery.phone) {
+            self.error(
+                format!("{path}.phone"),
+                "must be a phone number in international format, e.g. +9720000000",
+            );
+        }
+        self.not_empty(format!("{path}.zip"), &delivery.zip);
+        self.not_empty(format!("{path}.city"), &delivery.city);
+        self.not_empty(format!("{path}.address"), &delivery.address);
+        self.not_empty(format!("{path}.region"), &delivery.region);
+        if !is_email(&delivery.email) {
+            self.error(format!("{path}.email"), "must be a valid email address");
+        }
+    }
+
+    fn payment(&mut self, path: &str, payment: &Payment) {
+        self.not_empty(format!("{path}.transaction"), &payment.transaction);
+        if payment.currency().is_none() {
+            self.error(
+                format!("{path}.currency"),
+                "must be a known ISO 4217 currency code, e.g. USD",
+            );
+        }
+        self.not_empty(format!("{path}.provider"), &payment.provider);
+        self.money(format!("{path}.amount"), payment.amount);
+        if payment.payment_dt <= 0 {
+            self.error(
+                format!("{path}.payment_dt"),
+                "must be a positive unix timestamp",
+            );
+        }
+        self.not_empty(format!("{path}.bank"), &payment.bank);
+        self.money(format!("{path}.delivery_cost"), payment.delivery_cost);
+        self.money(format!("{path}.goods_total"), payment.goods_total);
+        self.money(format!("{path}.custom_fee"), payment.custom_fee);
+
+        match payment.goods_total.checked_add(payment.delivery_cost) {
+            Some(expected) if expected != payment.amount => self.error(
+                format!("{path}.amount"),
+                format!("must be equal to goods_total + delivery_cost ({expected})"),
+            ),
+            Some(_) => {}
+            None => self.error(
+                format!("{path}.amount"),
+                "goods_total + delivery_cost overflows",
+            ),
+        }
+    }
+
+    fn item(&mut self, path: &str, item: &Item) {
+        self.positive(format!("{path}.chrt_id"), item.chrt_id);
+        self.not_empty(format!("{path}.track_number"), &item.track_number);
+        self.money(format!("{path}.price"), item.price);
+        self.not_empty(format!("{path}.rid"), &item.rid);
+        self.not_empty(format!("{path}.name"), &item.name);
+        if !(0..=100).contains(&item.sale) {
+            self.error(
+                format!("{path}.sale"),
+                "must be a percentage between 0 and 100",
+            );
+        }
+        self.money(format!("{path}.total_price"), item.total_price);
+        self.positive(format!("{path}.nm_id"), item.nm_id);
+        self.not_empty(format!("{path}.brand"), &item.brand);
+        self.non_negative(format!("{path}.status"), item.status);
+
+        // the discounted price may be rounded either way
+        if let Some((floor, ceil)) = item.price.with_sale(item.sale) {
+            if item.total_price != floor && item.total_price != ceil {
+                self.error(
+                    format!("{path}.total_price"),
+                    format!("must be equal to price with the sale applied ({floor})"),
+                );
+            }
+        }
+    }
+
+    fn not_empty(&mut self, field: impl Into<String>, value: &str) {
+        if value.trim().is_empty() {
+            self.error(field, "must not be empty");
+        }
+    }
+
+    fn non_negative(&mut self, field: impl Into<String>, value: i64) {
+        if value < 0 {
+            self.error(field, "must not be negative");
+        }
+    }
+
+    fn money(&mut self, field: impl Into<String>, value: Money) {
+        if value.is_negative() {
+            self.error(field, "must not be negative");
+        }
+    }
+
+    fn positive(&mut self, field: impl Into<String>, value: i64) {
+        if value <= 0 {
+            self.error(field, "must be positive");
+        }
+    }
+
+    fn error(&mut self, field: impl Into<String>, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+    }
+}
+
+fn is_phone(phone: &str) -> bool {
+    let digits = phone.strip_prefix('+').unwrap_or(phone);
+    (7..=15).contains(&digits.len()) && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+fn is_email(email: &str) -> bool {
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use bb8::RunError;
+use parking_lot::Mutex;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::{
+    cache::{OrderCache, Snapshot},
+    schemas::{Delivery, Item, Order, Payment},
+    DbPool,
+};
+
+// amount of orders requested from the db at once
+const WARMUP_BATCH_SIZE: usize = 500;
+
+/// Progress of the cache warm-up reported by the readiness probe.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum WarmupStatus {
+    #[default]
+    Pending,
+    InProgress,
+    Done {
+        cached: usize,
+    },
+    // the service is still functional with a cold cache
+    Failed {
+        error: String,
+    },
+    Skipped,
+}
+
+impl WarmupStatus {
+    /// Whether the warm-up is over one way or another.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, WarmupStatus::Pending | WarmupStatus::InProgress)
+    }
+}
+
+/// Warm-up status shared between the warm-up task and the request handlers.
+#[derive(Debug, Default)]
+pub struct WarmupState(Mutex<WarmupStatus>);
+
+impl WarmupState {
+    pub fn get(&self) -> WarmupStatus {
+        self.0.lock().clone()
+    }
+
+    pub fn set(&self, status: WarmupStatus) {
+        *self.0.lock() = status;
+    }
+}
+
+/// Loads up to `limit` most recent orders into the cache.
+///
+/// Orders are inserted from the oldest to the newest, so the most recent ones
+/// end up being the last to be evicted. Returns the amount of cached orders.
+pub async fn warm_up_cache(
+    pool: &DbPool,
+    cache: &OrderCache,
+    limit: usize,
+) -> Result<usize, RunError<tokio_postgres::Error>> {
+    let conn = pool.get().await?;
+
+    let uid_rows = conn
+        .query(
+            "SELECT order_uid FROM orders ORDER BY date_created DESC NULLS LAST LIMIT $1",
+            &[&(limit as i64)],
+        )
+        .await?;
+    let mut order_uids: Vec<String> = uid_rows.iter().map(|row| row.get("order_uid")).collect();
+    order_uids.reverse();
+
+    let total = order_uids.len();
+    tracing::info!("warming up the cache with {} orders", total);
+
+    let mut cached = 0;
+    for batch in order_uids.chunks(WARMUP_BATCH_SIZE) {
+        // guarded like the loads, so the warm-up stays correct even if it overlaps the requests
+        let snapshots: Vec<Snapshot> = batch.iter().map(|uid| cache.snapshot(uid)).collect();
+        let items_rows = conn
+            .query("SELECT * FROM items WHERE order_uid = ANY($1)", &[&batch])
+            .await?;
+        let mut items: HashMap<String, Vec<Item>> = HashMap::new();
+        for row in &items_rows {
+            items
+                .entry(row.get("order_uid"))
+                .or_default()
+                .push(Item::from_row(row));
+        }
+
+        let mut deliveries: HashMap<String, Delivery> = conn
+            .query(
+                "SELECT * FROM deliveries WHERE order_uid = ANY($1)",
+                &[&batch],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get("order_uid"), Delivery::from_row(row)))
+            .collect();
+
+        let mut payments: HashMap<String, Payment> = conn
+            .query(
+                "SELECT * FROM payments WHERE order_uid = ANY($1)",
+                &[&batch],
+            )
+            .await?
+            .iter()
+            .map(|row| (row.get("order_uid"), Payment::from_row(row)))
+            .collect();
+
+        let order_rows = conn
+            .query("SELECT * FROM orders WHERE order_uid = ANY($1)", &[&batch])
+            .await?;
+        let mut orders: HashMap<String, Order> = HashMap::with_capacity(order_rows.len());
+        for row in &order_rows {
+            let order_uid: String = row.get("order_uid");
+            // an order without delivery or payment is incomplete and can't be served anyway
+            let (Some(delivery), Some(payment)) =
+                (deliveries.remove(&order_uid), payments.remove(&order_uid))
+            else {
+                tracing::warn!("skipping incomplete order {} during warm-up", order_uid);
+                continue;
+            };
+            let order_items = items.remove(&order_uid).unwrap_or_default();
+            orders.insert(
+                order_uid,
+                Order::from_row(row, delivery, payment, order_items),
+            );
+        }
+
+        // keep the recency order of the batch while inserting
+        for (order_uid, snapshot) in batch.iter().zip(snapshots) {
+            if let Some(order) = orders.remove(order_uid) {
+                cache.insert_unless_removed(order, snapshot);
+                cached += 1;
+            }
+        }
+
+        tracing::info!("warm-up progress: {}/{} orders", cached, total);
+    }
+
+    Ok(cached)
+}
@@ -0,0 +1,223 @@
+//! API keys and rate limits, the keys are kept by the in-memory repository.
+
+use std::{num::NonZeroUsize, sync::Arc};
+
+use axum::{
+    http::{Method, StatusCode},
+    Router,
+};
+use view_service::{
+    auth::{self, Auth, NewApiKey, Scope},
+    cache::{CacheConfig, OrderCache},
+    pii::Role,
+    repository::{MemoryRepository, OrderRepository},
+    router,
+    warmup::{WarmupState, WarmupStatus},
+    AppState,
+};
+
+use common::{order, send_with_headers};
+
+mod common;
+
+async fn key(repo: &dyn OrderRepository, scopes: &[Scope], burst: i32) -> String {
+    let new = NewApiKey {
+        name: format!("{scopes:?}"),
+        scopes: scopes.to_vec(),
+        rate_per_sec: 0.001,
+        burst,
+    };
+    auth::create_key(repo, &new).await.unwrap().0
+}
+
+// returns the keys with the read, write and admin scopes
+async fn app() -> (Router, [String; 3]) {
+    let repo = MemoryRepository::new();
+    let keys = [
+        key(&repo, &[Scope::Read], 100).await,
+        key(&repo, &[Scope::Write], 100).await,
+        key(&repo, &[Scope::Admin], 2).await,
+    ];
+    let state = AppState {
+        repo: Box::new(repo),
+        cache: OrderCache::new(CacheConfig {
+            max_entries: NonZeroUsize::new(100).unwrap(),
+            max_bytes: 1024 * 1024,
+            ttl: None,
+        }),
+        warmup: WarmupState::default(),
+        default_role: Role::Admin,
+        auth: Some(Auth::new()),
+    };
+    state.warmup.set(WarmupStatus::Skipped);
+    (router(Arc::new(state)), keys)
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_key_are_unauthorized() {
+    let (app, _) = app().await;
+
+    let (status, headers, body) =
+        send_with_headers(&app, &[], Method::GET, "/order/missing", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "unauthorized");
+    assert!(headers.contains_key("www-authenticate"));
+
+    let (status, _, _) = send_with_headers(
+        &app,
+        &[("x-api-key", "vs_unknown")],
+        Method::GET,
+        "/order/missing",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // probes stay open
+    let (status, _, _) = send_with_headers(&app, &[], Method::GET, "/healthz", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn scopes_separate_reads_from_writes() {
+    let (app, [read, write, admin]) = app().await;
+    let body = serde_json::to_value(order("scoped")).unwrap();
+
+    let (status, _, error) = send_with_headers(
+        &app,
+        &[("x-api-key", &read)],
+        Method::POST,
+        "/order",
+        Some(body.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(error["error"]["code"], "forbidden");
+
+    let bearer = format!("Bearer {write}");
+    let (status, _, _) = send_with_headers(
+        &app,
+        &[("authorization", &bearer)],
+        Method::POST,
+        "/order",
+        Some(body),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, _, _) = send_with_headers(
+        &app,
+        &[("x-api-key", &write)],
+        Method::GET,
+        "/order/scoped",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // the PII is shown to the admin keys only, whatever the X-Role header says
+    let (status, _, order) = send_with_headers(
+        &app,
+        &[("x-api-key", &read), ("x-role", "admin")],
+        Method::GET,
+        "/order/scoped",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(order["delivery"]["name"], "T*** T*****");
+
+    let (_, _, order) = send_with_headers(
+        &app,
+        &[("x-api-key", &admin)],
+        Method::GET,
+        "/order/scoped",
+        None,
+    )
+    .await;
+    assert_eq!(order["delivery"]["name"], "Test Testov");
+}
+
+#[tokio::test]
+async fn keys_are_rate_limited() {
+    let (app, [read, _, admin]) = app().await;
+
+    for remaining in ["1", "0"] {
+        let (status, headers, _) =
+            send_with_headers(&app, &[("x-api-key", &admin)], Method::GET, "/orders", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers["ratelimit-limit"], "2");
+        assert_eq!(headers["ratelimit-remaining"], remaining);
+    }
+
+    let (status, headers, body) =
+        send_with_headers(&app, &[("x-api-key", &admin)], Method::GET, "/orders", None).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(body["error"]["code"], "rate_limited");
+    assert!(
+        headers["retry-after"]
+            .to_str()
+            .unwrap()
+            .parse::<u64>()
+            .unwrap()
+            > 0
+    );
+    assert_eq!(headers["ratelimit-remaining"], "0");
+
+    // buckets are per key
+    let (status, _, _) =
+        send_with_headers(&app, &[("x-api-key", &read)], Method::GET, "/orders", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn pages_require_a_key() {
+    let (app, [read, write, _]) = app().await;
+    let body = serde_json::to_value(order("paged")).unwrap();
+    send_with_headers(
+        &app,
+        &[("x-api-key", &write)],
+        Method::POST,
+        "/order",
+        Some(body),
+    )
+    .await;
+
+    for uri in ["/", "/ui/order?order_uid=paged"] {
+        let (status, headers, _) =
+            send_with_headers(&app, &[("x-role", "viewer")], Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED, "{uri}");
+        assert!(headers.contains_key("www-authenticate"));
+
+        let (status, _, _) =
+            send_with_headers(&app, &[("x-api-key", &read)], Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn failed_attempts_are_rate_limited() {
+    let (app, [read, _, _]) = app().await;
+    let (status, _, _) =
+        send_with_headers(&app, &[("x-api-key", &read)], Method::GET, "/orders", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let mut limited = None;
+    for i in 0..200 {
+        let guess = format!("vs_guess{i}");
+        let (status, headers, _) =
+            send_with_headers(&app, &[("x-api-key", &guess)], Method::GET, "/orders", None).await;
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            limited = Some(headers);
+            break;
+        }
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let headers = limited.expect("failed attempts are not limited");
+    assert!(headers.contains_key("retry-after"));
+
+    // the known key is neither evicted nor limited by the guesses
+    let (status, _, _) =
+        send_with_headers(&app, &[("x-api-key", &read)], Method::GET, "/orders", None).await;
+    assert_eq!(status, StatusCode::OK);
+}
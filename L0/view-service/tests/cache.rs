@@ -0,0 +1,148 @@
+//! Concurrency of the order cache.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::http::StatusCode;
+use view_service::{
+    cache::{CacheConfig, OrderCache},
+    error::AppError,
+};
+
+use common::order;
+
+mod common;
+
+fn cache() -> Arc<OrderCache> {
+    Arc::new(OrderCache::new(CacheConfig {
+        max_entries: NonZeroUsize::new(100).unwrap(),
+        max_bytes: 1024 * 1024,
+        ttl: None,
+    }))
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_misses_share_a_single_load() {
+    let cache = cache();
+    let loads = Arc::new(AtomicUsize::new(0));
+
+    let requests: Vec<_> = (0..32)
+        .map(|_| {
+            let cache = cache.clone();
+            let loads = loads.clone();
+            tokio::spawn(async move {
+                cache
+                    .get_or_load("shared", || async {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Ok(order("shared"))
+                    })
+                    .await
+            })
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.await.unwrap().unwrap().order_uid, "shared");
+    }
+
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    assert!(cache.get("shared").is_some());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn load_errors_are_shared() {
+    let cache = cache();
+    let loads = Arc::new(AtomicUsize::new(0));
+
+    let requests: Vec<_> = (0..8)
+        .map(|_| {
+            let cache = cache.clone();
+            let loads = loads.clone();
+            tokio::spawn(async move {
+                cache
+                    .get_or_load("missing", || async {
+                        loads.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Err(AppError::NotFound("missing".to_string()))
+                    })
+                    .await
+            })
+        })
+        .collect();
+    for request in requests {
+        let Err(error) = request.await.unwrap() else {
+            panic!("missing order is loaded");
+        };
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+
+    assert_eq!(loads.load(Ordering::SeqCst), 1);
+    // failures are not cached
+    assert!(cache.get("missing").is_none());
+}
+
+#[tokio::test]
+async fn panicking_load_does_not_break_the_cache() {
+    let cache = cache();
+
+    let panicked = {
+        let cache = cache.clone();
+        tokio::spawn(async move {
+            cache
+                .get_or_load("fragile", || async { panic!("load failed") })
+                .await
+        })
+        .await
+    };
+    assert!(panicked.is_err());
+
+    let order = cache
+        .get_or_load("fragile", || async { Ok(order("fragile")) })
+        .await
+        .unwrap();
+    assert_eq!(order.order_uid, "fragile");
+    assert_eq!(cache.stats().entries, 1);
+}
+
+#[tokio::test]
+async fn load_overlapping_a_removal_is_not_cached() {
+    let cache = cache();
+
+    let loaded = cache
+        .get_or_load("changed", || async {
+            // e.g. the order is erased right after it has been read
+            cache.remove("changed");
+            Ok(order("changed"))
+        })
+        .await
+        .unwrap();
+    assert_eq!(loaded.order_uid, "changed");
+    assert!(cache.get("changed").is_none());
+
+    cache
+        .get_or_load("changed", || async { Ok(order("changed")) })
+        .await
+        .unwrap();
+    assert!(cache.get("changed").is_some());
+}
+
+#[test]
+fn insert_overlapping_a_removal_is_not_cached() {
+    let cache = cache();
+
+    // e.g. the warm-up reads an order that is deleted before the read is cached
+    let snapshot = cache.snapshot("deleted");
+    cache.remove("deleted");
+    cache.insert_unless_removed(order("deleted"), snapshot);
+    assert!(cache.get("deleted").is_none());
+
+    let snapshot = cache.snapshot("deleted");
+    cache.insert_unless_removed(order("deleted"), snapshot);
+    assert!(cache.get("deleted").is_some());
+}
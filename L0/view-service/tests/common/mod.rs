@@ -0,0 +1,105 @@
+//! Helpers shared by the test suites.
+// every test suite uses only a part of the helpers
+#![allow(dead_code)]
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, HeaderName, Method, Request, StatusCode},
+    Router,
+};
+use http_body_util::BodyExt;
+use serde_json::Value;
+use tower::ServiceExt;
+use view_service::schemas::Order;
+
+pub const MODEL_ORDER: &str = include_str!("../../model/model.json");
+
+/// The model order with another uid.
+pub fn order(order_uid: &str) -> Order {
+    let mut order: Order = serde_json::from_str(MODEL_ORDER).unwrap();
+    order.order_uid = order_uid.to_string();
+    order
+}
+
+/// Sends a request with a raw body through the router.
+pub async fn send_raw(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<String>,
+) -> (StatusCode, Value) {
+    respond(app, request(method, uri, body)).await
+}
+
+/// Sends a JSON request with the `X-Role` header.
+pub async fn send_as(
+    app: &Router,
+    role: &str,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    let (status, _, body) = send_with_headers(app, &[("x-role", role)], method, uri, body).await;
+    (status, body)
+}
+
+/// Sends a JSON request with extra headers, the response headers are returned as well.
+pub async fn send_with_headers(
+    app: &Router,
+    headers: &[(&str, &str)],
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, HeaderMap, Value) {
+    let mut request = request(method, uri, body.map(|body| body.to_string()));
+    for (name, value) in headers {
+        request
+            .headers_mut()
+            .insert(HeaderName::try_from(*name).unwrap(), value.parse().unwrap());
+    }
+    respond_with_headers(app, request).await
+}
+
+fn request(method: Method, uri: &str, body: Option<String>) -> Request<Body> {
+    let request = Request::builder().method(method).uri(uri);
+    match body {
+        Some(body) => request
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body)),
+        None => request.body(Body::empty()),
+    }
+    .unwrap()
+}
+
+async fn respond(app: &Router, request: Request<Body>) -> (StatusCode, Value) {
+    let (status, _, body) = respond_with_headers(app, request).await;
+    (status, body)
+}
+
+async fn respond_with_headers(
+    app: &Router,
+    request: Request<Body>,
+) -> (StatusCode, HeaderMap, Value) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let bytes = response.into_body().collect().await.unwrap().to_bytes();
+    // plain text and empty responses are returned as a JSON string
+    let body = serde_json::from_slice(&bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&bytes).into_owned()));
+    (status, headers, body)
+}
+
+pub async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    body: Option<Value>,
+) -> (StatusCode, Value) {
+    send_raw(app, method, uri, body.map(|body| body.to_string())).await
+}
+
+pub async fn create(app: &Router, order: &Order) -> StatusCode {
+    let body = serde_json::to_value(order).unwrap();
+    send(app, Method::POST, "/order", Some(body)).await.0
+}
@@ -0,0 +1,94 @@
+//! Merging of the configuration file with the arguments and the environment.
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::Parser;
+use view_service::config::{Args, Config};
+
+static FILES: AtomicUsize = AtomicUsize::new(0);
+
+// writes a file into a temp dir, it is left for the OS to clean up
+fn temp_file(contents: &str) -> PathBuf {
+    let name = format!(
+        "view-service-config-{}-{}",
+        process::id(),
+        FILES.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = env::temp_dir().join(name);
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn arguments_override_a_secret_file_of_the_config() {
+    let password_file = temp_file("from-file\n");
+    let config = temp_file(&format!(
+        "[postgres]\npassword_file = {:?}\n",
+        password_file.display().to_string()
+    ));
+
+    let file_only = Config::load(Args {
+        config: Some(config.clone()),
+        ..Args::default()
+    })
+    .unwrap();
+    assert_eq!(
+        file_only.postgres.get_password(),
+        Some("from-file".as_bytes())
+    );
+
+    let overridden = Config::load(Args {
+        config: Some(config),
+        pg_password: Some("from-env".to_string()),
+        ..Args::default()
+    })
+    .unwrap();
+    assert_eq!(
+        overridden.postgres.get_password(),
+        Some("from-env".as_bytes())
+    );
+
+    // both forms within the same layer are still ambiguous
+    let ambiguous = Config::load(Args {
+        pg_password: Some("from-env".to_string()),
+        pg_password_file: Some(password_file),
+        ..Args::default()
+    });
+    assert!(ambiguous.is_err());
+}
+
+#[test]
+fn arguments_turn_off_the_flags_of_the_config() {
+    let config = temp_file("[cache]\nskip_warmup = true\n\n[auth]\ndisabled = true\n");
+
+    let file_only = Config::load(Args {
+        config: Some(config.clone()),
+        ..Args::default()
+    })
+    .unwrap();
+    assert!(file_only.skip_warmup);
+    assert!(file_only.auth_disabled);
+
+    let path = config.display().to_string();
+    let args = Args::try_parse_from([
+        "view-service",
+        "--config",
+        &path,
+        "--skip-warmup=false",
+        "--disable-auth",
+        "no",
+    ])
+    .unwrap();
+    let overridden = Config::load(args).unwrap();
+    assert!(!overridden.skip_warmup);
+    assert!(!overridden.auth_disabled);
+
+    // a bare flag still turns the option on
+    let args = Args::try_parse_from(["view-service", "--skip-warmup"]).unwrap();
+    assert!(Config::load(args).unwrap().skip_warmup);
+}
@@ -0,0 +1,114 @@
+//! Stream consumer against a repository failing for a while.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use view_service::{
+    cache::{CacheConfig, OrderCache},
+    ingest::{self, Acker, DeadLetterSink, IngestError, OrderSource, SourceMessage},
+    pii::Role,
+    repository::MemoryRepository,
+    shutdown,
+    warmup::WarmupState,
+    AppState,
+};
+
+use common::order;
+
+mod common;
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+struct Messages(Vec<SourceMessage>);
+
+#[async_trait]
+impl OrderSource for Messages {
+    fn name(&self) -> &str {
+        "test messages"
+    }
+
+    async fn next_message(&mut self) -> Option<Result<SourceMessage, IngestError>> {
+        self.0.pop().map(Ok)
+    }
+}
+
+struct LogAcker(Log);
+
+#[async_trait]
+impl Acker for LogAcker {
+    async fn ack(self: Box<Self>) -> Result<(), IngestError> {
+        self.0.lock().unwrap().push("ack".to_string());
+        Ok(())
+    }
+
+    async fn nack(self: Box<Self>, _: Duration) -> Result<(), IngestError> {
+        self.0.lock().unwrap().push("nack".to_string());
+        Ok(())
+    }
+}
+
+struct LogSink(Log);
+
+#[async_trait]
+impl DeadLetterSink for LogSink {
+    async fn send(&self, _: &[u8], reason: &str) -> Result<(), IngestError> {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("dead letter: {reason}"));
+        Ok(())
+    }
+}
+
+// consumes a single message of the model order, returns what has happened to it
+async fn consume(repo: MemoryRepository) -> (Vec<String>, Arc<AppState>) {
+    let log = Log::default();
+    let payload = serde_json::to_vec(&order("streamed")).unwrap();
+    let message = SourceMessage::new(payload, Some(Box::new(LogAcker(log.clone()))));
+    let state = Arc::new(AppState {
+        repo: Box::new(repo),
+        cache: OrderCache::new(CacheConfig {
+            max_entries: NonZeroUsize::new(100).unwrap(),
+            max_bytes: 1024 * 1024,
+            ttl: None,
+        }),
+        warmup: WarmupState::default(),
+        default_role: Role::Admin,
+        auth: None,
+    });
+    let (_trigger, shutdown) = shutdown::channel();
+
+    ingest::run_consumer(
+        Box::new(Messages(vec![message])),
+        Box::new(LogSink(log.clone())),
+        state.clone(),
+        shutdown,
+    )
+    .await;
+    let log = log.lock().unwrap().clone();
+    (log, state)
+}
+
+#[tokio::test(start_paused = true)]
+async fn short_outages_are_retried() {
+    let repo = MemoryRepository::new();
+    repo.fail_inserts(3);
+
+    let (log, state) = consume(repo).await;
+    assert_eq!(log, ["ack"]);
+    assert!(state.repo.get("streamed").await.is_ok());
+}
+
+#[tokio::test(start_paused = true)]
+async fn long_outages_are_left_for_redelivery() {
+    let repo = MemoryRepository::new();
+    repo.fail_inserts(100);
+
+    let (log, state) = consume(repo).await;
+    assert_eq!(log, ["nack"]);
+    assert!(state.repo.get("streamed").await.is_err());
+}
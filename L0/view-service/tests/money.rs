@@ -0,0 +1,91 @@
+//! Money amounts and currencies.
+
+use view_service::{
+    money::{Amount, Currency, Money, MoneyError},
+    validation::validate_order,
+};
+
+use common::order;
+
+mod common;
+
+#[test]
+fn amounts_are_formatted_in_major_units() {
+    let usd = Currency::from_code("USD").unwrap();
+    assert_eq!(usd.format(Money::from_minor(1817)), "18.17 USD");
+    assert_eq!(usd.format(Money::from_minor(-5)), "-0.05 USD");
+
+    let jpy = Currency::from_code("JPY").unwrap();
+    assert_eq!(jpy.format(Money::from_minor(1817)), "1817 JPY");
+
+    let kwd = Currency::from_code("KWD").unwrap();
+    assert_eq!(kwd.format(Money::from_minor(1817)), "1.817 KWD");
+
+    assert_eq!(Currency::from_code("usd"), None);
+    assert_eq!(Currency::from_code("XXX"), None);
+}
+
+#[test]
+fn arithmetic_is_checked() {
+    let max = Money::from_minor(i64::MAX);
+    assert_eq!(max.checked_add(Money::from_minor(1)), None);
+    assert_eq!(Money::checked_sum([max, Money::from_minor(1)]), None);
+    assert_eq!(
+        Money::checked_sum([Money::from_minor(2), Money::from_minor(3)]),
+        Some(Money::from_minor(5))
+    );
+
+    assert_eq!(
+        Money::from_minor(453).with_sale(30),
+        Some((Money::from_minor(317), Money::from_minor(318)))
+    );
+    assert_eq!(Money::from_minor(100).with_sale(101), None);
+    assert_eq!(max.with_sale(30), None);
+}
+
+#[test]
+fn amounts_are_added_within_a_currency() {
+    let usd = Currency::from_code("USD").unwrap();
+    let rub = Currency::from_code("RUB").unwrap();
+    let cents = Amount::new(Money::from_minor(317), usd);
+
+    assert_eq!(
+        cents.checked_add(cents),
+        Ok(Amount::new(Money::from_minor(634), usd))
+    );
+    assert_eq!(
+        cents.checked_add(Amount::new(Money::from_minor(317), rub)),
+        Err(MoneyError::CurrencyMismatch(usd, rub))
+    );
+    assert_eq!(
+        Amount::new(Money::from_minor(i64::MAX), usd).checked_add(cents),
+        Err(MoneyError::Overflow(usd))
+    );
+}
+
+#[test]
+fn json_model_is_unchanged() {
+    let order = order("b563feb7b2b84b6test");
+    let json = serde_json::to_value(&order).unwrap();
+    assert_eq!(json["payment"]["amount"], 1817);
+    assert_eq!(json["items"][0]["total_price"], 317);
+    assert_eq!(order.payment.currency().map(Currency::code), Some("USD"));
+}
+
+#[test]
+fn unknown_currency_and_overflowing_totals_are_rejected() {
+    let mut unknown = order("b563feb7b2b84b6test");
+    unknown.payment.currency = "ABC".to_string();
+    let errors = validate_order(&unknown).unwrap_err();
+    assert_eq!(errors[0].field, "payment.currency");
+
+    let mut overflowing = order("b563feb7b2b84b6test");
+    overflowing.items.push(overflowing.items[0].clone());
+    overflowing.items[0].price = Money::from_minor(i64::MAX);
+    overflowing.items[0].sale = 0;
+    overflowing.items[0].total_price = Money::from_minor(i64::MAX);
+    let errors = validate_order(&overflowing).unwrap_err();
+    assert!(errors
+        .iter()
+        .any(|e| e.field == "items" && e.message.contains("overflows")));
+}
@@ -0,0 +1,195 @@
+//! The OpenAPI document against the structs and routes it describes.
+
+use std::{num::NonZeroUsize, sync::Arc};
+
+use axum::{
+    http::{Method, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use utoipa::OpenApi;
+use view_service::{
+    auth::Auth,
+    cache::{CacheConfig, OrderCache},
+    openapi::ApiDoc,
+    pii::Role,
+    repository::MemoryRepository,
+    router,
+    schemas::Order,
+    warmup::{WarmupState, WarmupStatus},
+    AppState,
+};
+
+use common::{order, send, send_raw};
+
+mod common;
+
+fn app(auth: Option<Auth>) -> Router {
+    let state = AppState {
+        repo: Box::new(MemoryRepository::new()),
+        cache: OrderCache::new(CacheConfig {
+            max_entries: NonZeroUsize::new(100).unwrap(),
+            max_bytes: 1024 * 1024,
+            ttl: None,
+        }),
+        warmup: WarmupState::default(),
+        default_role: Role::Admin,
+        auth,
+    };
+    state.warmup.set(WarmupStatus::Skipped);
+    router(Arc::new(state))
+}
+
+fn spec() -> Value {
+    serde_json::to_value(ApiDoc::openapi()).unwrap()
+}
+
+fn resolve<'a>(spec: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str() {
+        Some(reference) => {
+            let name = reference.rsplit('/').next().unwrap();
+            &spec["components"]["schemas"][name]
+        }
+        None => schema,
+    }
+}
+
+// describes the first difference between the value and the schema
+fn mismatch(spec: &Value, schema: &Value, value: &Value, at: &str) -> Option<String> {
+    let schema = resolve(spec, schema);
+    if let Some(variants) = schema["oneOf"].as_array() {
+        let matched = variants
+            .iter()
+            .any(|variant| mismatch(spec, variant, value, at).is_none());
+        return (!matched).then(|| format!("{at}: {value} matches no variant"));
+    }
+    if let Some(values) = schema["enum"].as_array() {
+        if !values.contains(value) {
+            return Some(format!("{at}: {value} is not one of {values:?}"));
+        }
+    }
+
+    let types = match &schema["type"] {
+        Value::String(ty) => vec![ty.as_str()],
+        Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+        other => return Some(format!("{at}: schema has no type: {other}")),
+    };
+    let ty = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    };
+    if !types.contains(&ty) {
+        return Some(format!("{at}: {ty} value, the schema has {types:?}"));
+    }
+
+    match value {
+        Value::Array(values) => values
+            .iter()
+            .enumerate()
+            .find_map(|(i, value)| mismatch(spec, &schema["items"], value, &format!("{at}[{i}]"))),
+        Value::Object(fields) => {
+            let properties = schema["properties"].as_object().unwrap();
+            let mut documented: Vec<&String> = properties.keys().collect();
+            let mut serialized: Vec<&String> = fields.keys().collect();
+            documented.sort();
+            serialized.sort();
+            if documented != serialized {
+                return Some(format!(
+                    "{at}: properties {documented:?}, serialized fields {serialized:?}"
+                ));
+            }
+            fields.iter().find_map(|(name, value)| {
+                mismatch(spec, &properties[name], value, &format!("{at}.{name}"))
+            })
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn schemas_match_the_serialized_order() {
+    let spec = spec();
+    let order = serde_json::to_value(order("b563feb7b2b84b6test")).unwrap();
+    let schema = serde_json::json!({ "$ref": "#/components/schemas/Order" });
+    assert_eq!(mismatch(&spec, &schema, &order, "order"), None);
+
+    // a made up field is caught
+    let mut extra = order.clone();
+    extra["delivery"]["floor"] = Value::from(3);
+    assert!(mismatch(&spec, &schema, &extra, "order").is_some());
+}
+
+#[test]
+fn required_properties_match_the_deserialization() {
+    let spec = spec();
+    let order = serde_json::to_value(order("b563feb7b2b84b6test")).unwrap();
+
+    // every object of the model order, nested ones as a JSON pointer
+    for (pointer, name) in [
+        ("", "Order"),
+        ("/delivery", "Delivery"),
+        ("/payment", "Payment"),
+        ("/items/0", "Item"),
+    ] {
+        let schema = &spec["components"]["schemas"][name];
+        let required = schema["required"].as_array().unwrap();
+        for property in schema["properties"].as_object().unwrap().keys() {
+            let mut missing = order.clone();
+            missing
+                .pointer_mut(pointer)
+                .unwrap()
+                .as_object_mut()
+                .unwrap()
+                .remove(property);
+            let accepted = serde_json::from_value::<Order>(missing).is_ok();
+            assert_eq!(
+                required.contains(&Value::from(property.as_str())),
+                !accepted,
+                "{name}.{property}"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn documented_operations_are_routed() {
+    let app = app(None);
+    let spec = spec();
+
+    let paths = spec["paths"].as_object().unwrap();
+    assert!(!paths.is_empty());
+    for (path, item) in paths {
+        let uri = path.replace(['{', '}'], "");
+        for method in item.as_object().unwrap().keys() {
+            let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+            let (status, body) = send_raw(&app, method.clone(), &uri, None).await;
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+            // the router answers unknown routes with an empty body, handlers with an error
+            if status == StatusCode::NOT_FOUND {
+                assert!(body["error"].is_object(), "{method} {path} is not routed");
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn document_and_viewer_are_open() {
+    let app = app(Some(Auth::new()));
+
+    let (status, body) = send(&app, Method::GET, "/openapi.json", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, spec());
+    assert!(body["paths"]["/order/{order_uid}"]["get"]["security"].is_array());
+    assert!(body["paths"]["/healthz"]["get"]["security"].is_null());
+
+    let (status, body) = send(&app, Method::GET, "/docs", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let html = body.as_str().unwrap();
+    assert!(html.contains("/order/{order_uid}/status"));
+    assert!(html.contains("Money"));
+}
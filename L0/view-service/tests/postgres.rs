@@ -0,0 +1,455 @@
+//! Runs the router against a throwaway local Postgres.
+//!
+//! The cluster is created with `initdb` in a temp dir for every test and removed afterwards.
+//! Binaries are looked up in `PG_BIN` or `PATH`. Postgres refuses to run as root,
+//! `PG_TEST_OS_USER` names the user to run it as in that case.
+//!
+//! The tests are ignored by default and run with `cargo test --test postgres -- --ignored`,
+//! a missing or failing Postgres fails them instead of skipping.
+
+use std::{
+    env, fs,
+    net::{Ipv4Addr, TcpListener},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use axum::{
+    http::{Method, StatusCode},
+    Router,
+};
+use bb8::Pool;
+use bb8_postgres::PostgresConnectionManager;
+use serde_json::{json, Value};
+use view_service::{
+    auth::{self, NewApiKey, Scope},
+    cache::{CacheConfig, OrderCache},
+    migrations,
+    pii::Role,
+    repository::{OrderRepository, PostgresRepository},
+    router,
+    tls::{self, TlsOptions},
+    warmup::{WarmupState, WarmupStatus},
+    AppState, DbPool,
+};
+
+use common::{create, order, send, send_raw, MODEL_ORDER};
+
+mod common;
+
+static CLUSTERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Postgres cluster living as long as the value.
+struct TempPostgres {
+    bin: PathBuf,
+    data: PathBuf,
+    port: u16,
+    os_user: Option<String>,
+}
+
+impl TempPostgres {
+    /// Creates and starts a cluster, panics if Postgres is not available.
+    fn start() -> TempPostgres {
+        let bin = find_bin().expect(
+            "initdb is not found, set PG_BIN to the Postgres binaries dir or add it to PATH",
+        );
+        let data = env::temp_dir().join(format!(
+            "view-service-test-{}-{}",
+            std::process::id(),
+            CLUSTERS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let port = free_port();
+        let pg = TempPostgres {
+            bin,
+            data,
+            port,
+            os_user: env::var("PG_TEST_OS_USER").ok(),
+        };
+
+        let data = pg.data.to_str().unwrap();
+        let initdb = pg.run(
+            "initdb",
+            &[
+                "-D",
+                data,
+                "-U",
+                "postgres",
+                "-A",
+                "trust",
+                "-E",
+                "UTF8",
+                "--no-sync",
+            ],
+        );
+        assert!(
+            initdb.status.success(),
+            "initdb failed, set PG_TEST_OS_USER to an unprivileged user when running as root\n{}",
+            String::from_utf8_lossy(&initdb.stderr)
+        );
+
+        let options = format!("-p {port} -k {data} -c listen_addresses=127.0.0.1 -c fsync=off");
+        let log = pg.data.join("postgres.log");
+        let started = pg.run(
+            "pg_ctl",
+            &[
+                "-D",
+                data,
+                "-o",
+                &options,
+                "-l",
+                log.to_str().unwrap(),
+                "-w",
+                "start",
+            ],
+        );
+        assert!(
+            started.status.success(),
+            "failed to start postgres: {}",
+            fs::read_to_string(&log).unwrap_or_default()
+        );
+        pg
+    }
+
+    fn run(&self, program: &str, args: &[&str]) -> Output {
+        let program = self.bin.join(program);
+        let mut command = match &self.os_user {
+            Some(user) => {
+                let mut command = Command::new("runuser");
+                command.args(["-u", user, "--"]).arg(program);
+                command
+            }
+            None => Command::new(program),
+        };
+        command
+            .args(args)
+            .output()
+            .expect("failed to run a postgres binary")
+    }
+
+    /// Pool connected to the cluster with the migrations applied.
+    async fn pool(&self) -> DbPool {
+        let mut config = tokio_postgres::Config::new();
+        config
+            .host("127.0.0.1")
+            .port(self.port)
+            .user("postgres")
+            .dbname("postgres");
+        let tls = tls::make_connector(&TlsOptions::default()).unwrap();
+        let pool = Pool::builder()
+            .max_size(4)
+            .build(PostgresConnectionManager::new(config, tls))
+            .await
+            .unwrap();
+
+        let mut conn = pool.get().await.unwrap();
+        migrations::run(&mut conn).await.unwrap();
+        drop(conn);
+        pool
+    }
+}
+
+impl Drop for TempPostgres {
+    fn drop(&mut self) {
+        let data = self.data.to_str().unwrap();
+        self.run("pg_ctl", &["-D", data, "-m", "immediate", "-w", "stop"]);
+        let _ = fs::remove_dir_all(&self.data);
+    }
+}
+
+fn find_bin() -> Option<PathBuf> {
+    let dirs = match env::var_os("PG_BIN") {
+        Some(dir) => vec![PathBuf::from(dir)],
+        None => env::split_paths(&env::var_os("PATH")?).collect(),
+    };
+    dirs.into_iter()
+        .find(|dir| Path::new(dir).join("initdb").is_file())
+}
+
+fn free_port() -> u16 {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+// every router gets an empty cache, so the orders are read from the db
+fn app(pool: &DbPool) -> Router {
+    let state = AppState {
+        repo: Box::new(PostgresRepository::new(pool.clone())),
+        cache: OrderCache::new(CacheConfig {
+            max_entries: NonZeroUsize::new(100).unwrap(),
+            max_bytes: 1024 * 1024,
+            ttl: None,
+        }),
+        warmup: WarmupState::default(),
+        default_role: Role::Admin,
+        auth: None,
+    };
+    state.warmup.set(WarmupStatus::Skipped);
+    router(Arc::new(state))
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn model_order_round_trips() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+
+    let (status, _) = send_raw(
+        &app(&pool),
+        Method::POST,
+        "/order",
+        Some(MODEL_ORDER.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, mut stored) =
+        send(&app(&pool), Method::GET, "/order/b563feb7b2b84b6test", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored["status"], "created");
+    stored.as_object_mut().unwrap().remove("status");
+    let model: Value = serde_json::from_str(MODEL_ORDER).unwrap();
+    assert_eq!(stored, model);
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn duplicate_order_is_resolved() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let original = order("duplicate");
+
+    assert_eq!(create(&app(&pool), &original).await, StatusCode::CREATED);
+    // identical resubmission is answered with the stored order
+    let body = serde_json::to_value(&original).unwrap();
+    let (status, stored) = send(&app(&pool), Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stored["order_uid"], "duplicate");
+
+    let mut changed = original.clone();
+    changed.delivery.city = "Elsewhere".to_string();
+    let body = serde_json::to_value(&changed).unwrap();
+    let (status, error) = send(&app(&pool), Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        error["error"]["details"]["differences"][0]["field"],
+        "delivery.city"
+    );
+
+    let conn = pool.get().await.unwrap();
+    let count: i64 = conn
+        .query_one(
+            "SELECT count(*) FROM orders WHERE order_uid = 'duplicate'",
+            &[],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(count, 1);
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn missing_order_is_not_found() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+
+    let (status, error) = send(&app(&pool), Method::GET, "/order/missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(error["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn malformed_json_is_rejected() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let app = app(&pool);
+
+    let (status, error) = send_raw(
+        &app,
+        Method::POST,
+        "/order",
+        Some("{\"order_uid\": ".to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(error["error"]["code"], "invalid_body");
+
+    // valid JSON of another shape
+    let (status, error) = send_raw(
+        &app,
+        Method::POST,
+        "/order",
+        Some("{\"order_uid\": 1}".to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(error["error"]["code"], "invalid_body");
+
+    let conn = pool.get().await.unwrap();
+    let count: i64 = conn
+        .query_one("SELECT count(*) FROM orders", &[])
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn orders_without_date_are_not_listed() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let app = app(&pool);
+    create(&app, &order("dated")).await;
+    create(&app, &order("undated")).await;
+
+    // rows written before the validation may have no date
+    let conn = pool.get().await.unwrap();
+    conn.execute(
+        "UPDATE orders SET date_created = NULL WHERE order_uid = 'undated'",
+        &[],
+    )
+    .await
+    .unwrap();
+
+    let (status, page) = send(&app, Method::GET, "/orders", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["orders"].as_array().unwrap().len(), 1);
+    assert_eq!(page["orders"][0]["order_uid"], "dated");
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn stats_keep_the_currencies_apart() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let app = app(&pool);
+    for (i, currency) in ["USD", "RUB", "USD"].into_iter().enumerate() {
+        let mut order = order(&format!("currency-{i}"));
+        order.payment.currency = currency.to_string();
+        assert_eq!(create(&app, &order).await, StatusCode::CREATED);
+    }
+
+    let (status, brands) = send(&app, Method::GET, "/stats/brands", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        brands["rows"],
+        json!([
+            {"brand": "Vivienne Sabo", "currency": "USD", "items": 2, "total_price": 634},
+            {"brand": "Vivienne Sabo", "currency": "RUB", "items": 1, "total_price": 317},
+        ])
+    );
+
+    let (status, costs) = send(&app, Method::GET, "/stats/delivery-costs", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        costs["rows"],
+        json!([
+            {"region": "Kraiot", "currency": "RUB", "orders": 1, "avg_delivery_cost": 1500.0},
+            {"region": "Kraiot", "currency": "USD", "orders": 2, "avg_delivery_cost": 1500.0},
+        ])
+    );
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn migrations_are_applied_once() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+
+    let mut conn = pool.get().await.unwrap();
+    assert_eq!(
+        migrations::current_version(&conn).await.unwrap(),
+        migrations::latest_version()
+    );
+    assert!(migrations::run(&mut conn).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn customer_erasure_is_audited() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let app = app(&pool);
+    create(&app, &order("erased")).await;
+
+    let (status, erasure) = send(
+        &app,
+        Method::POST,
+        "/customers/test/erasure",
+        Some(serde_json::json!({"reason": "customer request"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(erasure["order_uids"], serde_json::json!(["erased"]));
+
+    let conn = pool.get().await.unwrap();
+    let row = conn
+        .query_one(
+            "SELECT name, phone, email, region FROM deliveries WHERE order_uid = 'erased'",
+            &[],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, String>("name"), "[erased]");
+    assert_eq!(row.get::<_, String>("phone"), "[erased]");
+    assert_eq!(row.get::<_, String>("email"), "[erased]");
+    assert_eq!(row.get::<_, String>("region"), "Kraiot");
+
+    let row = conn
+        .query_one(
+            "SELECT customer_id, order_uids, reason FROM pii_erasures WHERE id = $1",
+            &[&erasure["id"].as_i64().unwrap()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(row.get::<_, String>("customer_id"), "test");
+    assert_eq!(row.get::<_, Vec<String>>("order_uids"), ["erased"]);
+    assert_eq!(row.get::<_, String>("reason"), "customer request");
+
+    // the audit is append-only
+    assert!(conn.execute("DELETE FROM pii_erasures", &[]).await.is_err());
+}
+
+#[tokio::test]
+#[ignore = "needs Postgres binaries, run with --ignored"]
+async fn api_keys_are_stored_hashed() {
+    let pg = TempPostgres::start();
+    let pool = pg.pool().await;
+    let repo = PostgresRepository::new(pool.clone());
+
+    let new = NewApiKey {
+        name: "ingest".to_string(),
+        scopes: vec![Scope::Read, Scope::Write],
+        rate_per_sec: 5.0,
+        burst: 10,
+    };
+    let (key, stored) = auth::create_key(&repo, &new).await.unwrap();
+    assert!(key.starts_with("vs_"));
+
+    let found = repo.api_key(&auth::hash_key(&key)).await.unwrap().unwrap();
+    assert_eq!(found.id, stored.id);
+    assert_eq!(found.scopes, [Scope::Read, Scope::Write]);
+
+    let conn = pool.get().await.unwrap();
+    let stored_keys: i64 = conn
+        .query_one(
+            "SELECT count(*) FROM api_keys WHERE key_hash = convert_to($1, 'UTF8')",
+            &[&key],
+        )
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(stored_keys, 0);
+
+    conn.execute("UPDATE api_keys SET revoked_at = now()", &[])
+        .await
+        .unwrap();
+    assert!(repo.api_key(&auth::hash_key(&key)).await.unwrap().is_none());
+}
@@ -0,0 +1,452 @@
+//! Exercises the router with the in-memory repository, no db is needed.
+
+use std::{num::NonZeroUsize, sync::Arc};
+
+use axum::{
+    http::{Method, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use view_service::{
+    cache::{CacheConfig, OrderCache},
+    money::Money,
+    pii::Role,
+    repository::MemoryRepository,
+    router,
+    warmup::{WarmupState, WarmupStatus},
+    AppState,
+};
+
+use common::{create, order, send, send_as};
+
+mod common;
+
+fn app() -> Router {
+    let state = AppState {
+        repo: Box::new(MemoryRepository::new()),
+        cache: OrderCache::new(CacheConfig {
+            max_entries: NonZeroUsize::new(100).unwrap(),
+            max_bytes: 1024 * 1024,
+            ttl: None,
+        }),
+        warmup: WarmupState::default(),
+        default_role: Role::Admin,
+        auth: None,
+    };
+    state.warmup.set(WarmupStatus::Skipped);
+    router(Arc::new(state))
+}
+
+#[tokio::test]
+async fn created_order_is_returned() {
+    let app = app();
+    assert_eq!(create(&app, &order("first")).await, StatusCode::CREATED);
+
+    let (status, body) = send(&app, Method::GET, "/order/first", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["order_uid"], "first");
+    assert_eq!(body["status"], "created");
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn missing_order_is_not_found() {
+    let (status, body) = send(&app(), Method::GET, "/order/missing", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn invalid_order_is_rejected() {
+    let mut invalid = order("invalid");
+    invalid.payment.currency = "dollars".to_string();
+    let body = serde_json::to_value(&invalid).unwrap();
+
+    let (status, body) = send(&app(), Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["details"][0]["field"], "payment.currency");
+}
+
+#[tokio::test]
+async fn resubmission_is_idempotent() {
+    let app = app();
+    let original = order("resubmitted");
+    assert_eq!(create(&app, &original).await, StatusCode::CREATED);
+    assert_eq!(create(&app, &original).await, StatusCode::OK);
+
+    let mut changed = original.clone();
+    changed.delivery.city = "Elsewhere".to_string();
+    let body = serde_json::to_value(&changed).unwrap();
+    let (status, body) = send(&app, Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body["error"]["details"]["differences"][0]["field"],
+        "delivery.city"
+    );
+}
+
+#[tokio::test]
+async fn orders_are_listed_by_pages() {
+    let app = app();
+    for i in 0..3 {
+        let mut order = order(&format!("listed-{i}"));
+        order.date_created += chrono::Duration::hours(i);
+        create(&app, &order).await;
+    }
+
+    let (status, first) = send(&app, Method::GET, "/orders?limit=2", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let uids: Vec<_> = first["orders"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["order_uid"].clone())
+        .collect();
+    assert_eq!(uids, ["listed-2", "listed-1"]);
+
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (_, second) = send(
+        &app,
+        Method::GET,
+        &format!("/orders?limit=2&cursor={cursor}"),
+        None,
+    )
+    .await;
+    assert_eq!(second["orders"][0]["order_uid"], "listed-0");
+    assert_eq!(second["next_cursor"], Value::Null);
+
+    let (_, filtered) = send(&app, Method::GET, "/orders?customer_id=nobody", None).await;
+    assert!(filtered["orders"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn status_follows_the_lifecycle() {
+    let app = app();
+    create(&app, &order("lifecycle")).await;
+
+    let (status, body) = send(
+        &app,
+        Method::PATCH,
+        "/order/lifecycle/status",
+        Some(json!({"status": "shipped"})),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body["error"]["details"]["allowed"],
+        json!(["paid", "cancelled"])
+    );
+
+    for next in ["paid", "shipped"] {
+        let (status, _) = send(
+            &app,
+            Method::PATCH,
+            "/order/lifecycle/status",
+            Some(json!({"status": next})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    // the cached order is refreshed after the change
+    let (_, order) = send(&app, Method::GET, "/order/lifecycle", None).await;
+    assert_eq!(order["status"], "shipped");
+
+    let (status, history) = send(&app, Method::GET, "/order/lifecycle/history", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let statuses: Vec<_> = history["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|e| e["to_status"].clone())
+        .collect();
+    assert_eq!(statuses, ["created", "paid", "shipped"]);
+}
+
+#[tokio::test]
+async fn deleted_order_is_gone() {
+    let app = app();
+    create(&app, &order("deleted")).await;
+    // puts the order into the cache
+    send(&app, Method::GET, "/order/deleted", None).await;
+
+    let (status, _) = send(&app, Method::DELETE, "/order/deleted", None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = send(&app, Method::GET, "/order/deleted", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, Method::DELETE, "/order/deleted", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn atomic_batch_is_rejected_as_a_whole() {
+    let app = app();
+    let mut invalid = order("batch-invalid");
+    invalid.items.clear();
+    let batch = json!([order("batch-valid"), invalid]);
+
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/orders/batch?mode=atomic",
+        Some(batch.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body["error"]["details"]["results"][0]["status"],
+        "not_inserted"
+    );
+    let (status, _) = send(&app, Method::GET, "/order/batch-valid", None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, body) = send(&app, Method::POST, "/orders/batch", Some(batch)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["created"], 1);
+    assert_eq!(body["failed"], 1);
+}
+
+#[tokio::test]
+async fn batch_conflicts_hide_the_pii_from_viewers() {
+    let app = app();
+    let mut changed = order("batch-masked");
+    changed.delivery.phone = "+9721111111".to_string();
+    let batch = json!([order("batch-masked"), changed]);
+
+    let (status, body) = send_as(&app, "viewer", Method::POST, "/orders/batch", Some(batch)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["results"][1]["status"], "conflict");
+    assert_eq!(
+        body["results"][1]["details"],
+        json!([{ "field": "delivery", "existing": "[masked]", "received": "[masked]" }])
+    );
+}
+
+#[tokio::test]
+async fn probes_report_readiness() {
+    let app = app();
+    let (status, _) = send(&app, Method::GET, "/healthz", None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(&app, Method::GET, "/readyz", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["checks"]["migrations"]["up_to_date"], true);
+}
+
+#[tokio::test]
+async fn stats_are_aggregated_within_the_range() {
+    let app = app();
+    for (i, brand) in ["A", "B", "B"].into_iter().enumerate() {
+        let mut order = order(&format!("stats-{i}"));
+        order.date_created += chrono::Duration::days(i as i64);
+        order.items[0].brand = brand.to_string();
+        create(&app, &order).await;
+    }
+
+    let (status, brands) = send(&app, Method::GET, "/stats/brands", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        brands["rows"][0],
+        json!({"brand": "B", "currency": "USD", "items": 2, "total_price": 634})
+    );
+
+    let (_, revenue) = send(
+        &app,
+        Method::GET,
+        "/stats/revenue?created_from=2021-11-27T00:00:00Z",
+        None,
+    )
+    .await;
+    let days: Vec<_> = revenue["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row["day"].clone())
+        .collect();
+    assert_eq!(days, ["2021-11-27", "2021-11-28"]);
+
+    let (_, services) = send(&app, Method::GET, "/stats/delivery-services", None).await;
+    assert_eq!(
+        services["rows"],
+        json!([{"delivery_service": "meest", "orders": 3, "items": 3}])
+    );
+
+    let (status, _) = send(&app, Method::GET, "/stats/products?limit=1000", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn amounts_in_different_currencies_are_not_summed() {
+    let app = app();
+    for (i, currency) in ["USD", "RUB", "USD"].into_iter().enumerate() {
+        let mut order = order(&format!("currency-{i}"));
+        order.payment.currency = currency.to_string();
+        assert_eq!(create(&app, &order).await, StatusCode::CREATED);
+    }
+
+    let (status, brands) = send(&app, Method::GET, "/stats/brands", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        brands["rows"],
+        json!([
+            {"brand": "Vivienne Sabo", "currency": "USD", "items": 2, "total_price": 634},
+            {"brand": "Vivienne Sabo", "currency": "RUB", "items": 1, "total_price": 317},
+        ])
+    );
+
+    let (_, products) = send(&app, Method::GET, "/stats/products", None).await;
+    assert_eq!(products["rows"].as_array().unwrap().len(), 2);
+
+    let (_, costs) = send(&app, Method::GET, "/stats/delivery-costs", None).await;
+    assert_eq!(
+        costs["rows"],
+        json!([
+            {"region": "Kraiot", "currency": "RUB", "orders": 1, "avg_delivery_cost": 1500.0},
+            {"region": "Kraiot", "currency": "USD", "orders": 2, "avg_delivery_cost": 1500.0},
+        ])
+    );
+}
+
+#[tokio::test]
+async fn overflowing_sums_are_rejected() {
+    let app = app();
+    for i in 0..2 {
+        let mut order = order(&format!("huge-{i}"));
+        order.date_created += chrono::Duration::hours(i);
+        order.payment.delivery_cost = Money::from_minor(i64::MAX - 317);
+        order.payment.amount = Money::from_minor(i64::MAX);
+        assert_eq!(create(&app, &order).await, StatusCode::CREATED);
+    }
+
+    for uri in ["/stats/revenue", "/stats/delivery-costs"] {
+        let (status, body) = send(&app, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        assert!(body["error"]["message"]
+            .as_str()
+            .unwrap()
+            .contains("overflow"));
+    }
+    // one of the orders still fits
+    let (status, _) = send(
+        &app,
+        Method::GET,
+        "/stats/revenue?created_to=2021-11-26T06:22:19Z",
+        None,
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn delivery_pii_is_masked_for_viewers() {
+    let app = app();
+    create(&app, &order("masked")).await;
+
+    let (status, body) = send_as(&app, "viewer", Method::GET, "/order/masked", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["delivery"],
+        json!({
+            "name": "T*** T*****",
+            "phone": "+********00",
+            "zip": "*******",
+            "city": "Kiryat Mozkin",
+            "address": "******* **** **",
+            "region": "Kraiot",
+            "email": "t***@gmail.com",
+        })
+    );
+
+    let (_, body) = send_as(&app, "admin", Method::GET, "/order/masked", None).await;
+    assert_eq!(body["delivery"]["name"], "Test Testov");
+
+    let (status, _) = send_as(&app, "root", Method::GET, "/order/masked", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // conflicts never show the stored PII
+    let mut changed = order("masked");
+    changed.delivery.phone = "+9721111111".to_string();
+    let body = serde_json::to_value(&changed).unwrap();
+    let (status, body) = send(&app, Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(
+        body["error"]["details"]["differences"][0]["existing"],
+        "+********00"
+    );
+
+    // viewers only learn that the delivery PII differs
+    changed.delivery.name = "Other Name".to_string();
+    changed.delivery.email = "other@gmail.com".to_string();
+    changed.delivery.city = "Haifa".to_string();
+    let body = serde_json::to_value(&changed).unwrap();
+    let (status, body) = send_as(&app, "viewer", Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let fields: Vec<&str> = body["error"]["details"]["differences"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["delivery.city", "delivery"]);
+    let differences = body["error"]["details"]["differences"].to_string();
+    assert!(!differences.contains("Other Name"));
+    assert!(!differences.contains("T*** T*****"));
+
+    // the identical resubmission is echoed masked
+    let body = serde_json::to_value(order("masked")).unwrap();
+    let (status, body) = send_as(&app, "viewer", Method::POST, "/order", Some(body)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["delivery"]["name"], "T*** T*****");
+}
+
+#[tokio::test]
+async fn customer_delivery_data_is_erased() {
+    let app = app();
+    for uid in ["erased-1", "erased-2"] {
+        create(&app, &order(uid)).await;
+        // the cached copy has to be evicted
+        send(&app, Method::GET, &format!("/order/{uid}"), None).await;
+    }
+    let mut other = order("kept");
+    other.customer_id = "other".to_string();
+    create(&app, &other).await;
+
+    let request = json!({"reason": "customer request"});
+    let (status, _) = send_as(
+        &app,
+        "viewer",
+        Method::POST,
+        "/customers/test/erasure",
+        Some(request.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, erasure) = send(
+        &app,
+        Method::POST,
+        "/customers/test/erasure",
+        Some(request.clone()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(erasure["order_uids"], json!(["erased-1", "erased-2"]));
+    assert_eq!(erasure["reason"], "customer request");
+    assert_eq!(erasure["requested_by"], "admin");
+
+    let (_, body) = send(&app, Method::GET, "/order/erased-1", None).await;
+    assert_eq!(body["delivery"]["name"], "[erased]");
+    assert_eq!(body["delivery"]["email"], "[erased]");
+    assert_eq!(body["delivery"]["region"], "Kraiot");
+    let (_, body) = send(&app, Method::GET, "/order/kept", None).await;
+    assert_eq!(body["delivery"]["name"], "Test Testov");
+
+    let (status, _) = send(
+        &app,
+        Method::POST,
+        "/customers/nobody/erasure",
+        Some(request),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}